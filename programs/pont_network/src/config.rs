@@ -0,0 +1,423 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{Mint, Token, TokenAccount},
+};
+
+use crate::fundraising::FundraisingAccount;
+use crate::program::PontNetwork;
+
+pub const BASIS_POINTS: u64 = 10_000;
+pub const MAX_FEE_MINTS: usize = 8;
+pub const MAX_LOCK_TIERS: usize = 8;
+
+#[account]
+pub struct ProtocolConfig {
+    pub admin: Pubkey,
+    pub fee_schedule: FeeSchedule,
+    // Seconds between `request_unstake` and `withdraw_unstaked`
+    pub unstake_cooldown: i64,
+    pub fee_mints: Vec<FeeMint>,
+    pub lock_tiers: Vec<LockTier>,
+}
+
+impl ProtocolConfig {
+    pub const SIZE: usize = 32
+        + FeeSchedule::SIZE
+        + 8
+        + 4
+        + MAX_FEE_MINTS * FeeMint::SIZE
+        + 4
+        + MAX_LOCK_TIERS * LockTier::SIZE;
+
+    pub fn fee_mint(&self, mint: &Pubkey) -> Result<&FeeMint> {
+        self.fee_mints
+            .iter()
+            .find(|fee_mint| fee_mint.mint == *mint)
+            .ok_or(error!(ConfigErrors::FeeMintNotWhitelisted))
+    }
+
+    /// Reward multiplier for staking with `lock_duration`. Unlocked stakes
+    /// (a duration of 0) always earn 1x.
+    pub fn reward_multiplier_bps(&self, lock_duration: i64) -> Result<u16> {
+        if lock_duration == 0 {
+            return Ok(BASIS_POINTS as u16);
+        }
+
+        self.lock_tiers
+            .iter()
+            .find(|lock_tier| lock_tier.lock_duration == lock_duration)
+            .map(|lock_tier| lock_tier.reward_multiplier_bps)
+            .ok_or(error!(ConfigErrors::LockTierNotFound))
+    }
+}
+
+/// Stakes locked for `lock_duration` seconds earn rewards on their amount times
+/// `reward_multiplier_bps` / 10_000.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LockTier {
+    pub lock_duration: i64,
+    pub reward_multiplier_bps: u16,
+}
+
+impl LockTier {
+    pub const SIZE: usize = 8 + 2;
+}
+
+/// SPL mint accepted for fingerprint fees, with fees in the mint's base units.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct FeeMint {
+    pub mint: Pubkey,
+    pub fingerprint_fee: u64,
+    pub batch_fee: u64,
+}
+
+impl FeeMint {
+    pub const SIZE: usize = 32 + 8 + 8;
+}
+
+/// Protocol fees in lamports, paid into the fundraising account.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct FeeSchedule {
+    pub fingerprint_fee: u64,
+    pub batch_fee: u64,
+    pub data_account_fee: u64,
+    pub observer_approval_fee: u64,
+    // Batches of at least this many fingerprints get the volume discount, 0 disables it
+    pub volume_discount_threshold: u32,
+    pub volume_discount_bps: u16,
+    // Price of one prepaid fingerprint credit, 0 disables credit sales
+    pub credit_price: u64,
+}
+
+impl FeeSchedule {
+    pub const SIZE: usize = 8 + 8 + 8 + 8 + 4 + 2 + 8;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.volume_discount_bps as u64 <= BASIS_POINTS,
+            ConfigErrors::InvalidBasisPoints
+        );
+        Ok(())
+    }
+
+    /// `flat_fee` plus `fingerprint_fee` for every fingerprint, with the volume discount
+    /// applied to the per-fingerprint part. Used for lamport and SPL fees alike.
+    pub fn fee_for(
+        &self,
+        fingerprint_fee: u64,
+        flat_fee: u64,
+        fingerprint_count: usize,
+    ) -> Result<u64> {
+        let mut fingerprint_fees = fingerprint_fee as u128 * fingerprint_count as u128;
+
+        if self.volume_discount_threshold > 0
+            && fingerprint_count >= self.volume_discount_threshold as usize
+        {
+            fingerprint_fees = fingerprint_fees
+                * (BASIS_POINTS - self.volume_discount_bps as u64) as u128
+                / BASIS_POINTS as u128;
+        }
+
+        u64::try_from(fingerprint_fees + flat_fee as u128)
+            .map_err(|_| error!(ConfigErrors::FeeOverflow))
+    }
+}
+
+/// Only the program's upgrade authority can initialize the config, so nobody
+/// can front-run the deployment and make themselves admin.
+#[derive(Accounts)]
+pub struct InitializeProtocolConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, PontNetwork>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ ConfigErrors::NotUpgradeAuthority
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        init,
+        seeds = [b"protocol_config"],
+        bump,
+        payer = admin,
+        space = 8 + ProtocolConfig::SIZE
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocolConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFeeMint<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetFeeMint<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    // Holds the mint's fee pool, added with the mint
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    pub fee_mint: Account<'info, Mint>,
+    // Fees paid in `fee_mint` are held here until stakers claim them
+    #[account(
+        init_if_needed,
+        payer = admin,
+        associated_token::mint = fee_mint,
+        associated_token::authority = fundraising_account,
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn initialize_protocol_config(
+    ctx: Context<InitializeProtocolConfig>,
+    fee_schedule: FeeSchedule,
+) -> Result<()> {
+    fee_schedule.validate()?;
+
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.admin = ctx.accounts.admin.key();
+    protocol_config.fee_schedule = fee_schedule.clone();
+    protocol_config.unstake_cooldown = 0;
+    protocol_config.fee_mints = Vec::new();
+    protocol_config.lock_tiers = Vec::new();
+
+    emit!(ProtocolConfigUpdated {
+        admin: protocol_config.admin,
+        fee_schedule,
+    });
+
+    Ok(())
+}
+
+pub fn update_fee_schedule(
+    ctx: Context<UpdateProtocolConfig>,
+    fee_schedule: FeeSchedule,
+) -> Result<()> {
+    fee_schedule.validate()?;
+
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.fee_schedule = fee_schedule.clone();
+
+    emit!(ProtocolConfigUpdated {
+        admin: protocol_config.admin,
+        fee_schedule,
+    });
+
+    Ok(())
+}
+
+pub fn set_protocol_admin(ctx: Context<UpdateProtocolConfig>, new_admin: Pubkey) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    protocol_config.admin = new_admin;
+
+    emit!(ProtocolConfigUpdated {
+        admin: new_admin,
+        fee_schedule: protocol_config.fee_schedule.clone(),
+    });
+
+    Ok(())
+}
+
+pub fn set_unstake_cooldown(
+    ctx: Context<UpdateProtocolConfig>,
+    unstake_cooldown: i64,
+) -> Result<()> {
+    require!(unstake_cooldown >= 0, ConfigErrors::InvalidUnstakeCooldown);
+
+    ctx.accounts.protocol_config.unstake_cooldown = unstake_cooldown;
+
+    emit!(UnstakeCooldownUpdated { unstake_cooldown });
+
+    Ok(())
+}
+
+/// Replaces the lock tiers offered to stakers. Existing locks keep the
+/// multiplier they were staked with.
+pub fn set_lock_tiers(ctx: Context<UpdateProtocolConfig>, lock_tiers: Vec<LockTier>) -> Result<()> {
+    require!(
+        lock_tiers.len() <= MAX_LOCK_TIERS,
+        ConfigErrors::TooManyLockTiers
+    );
+    for (i, lock_tier) in lock_tiers.iter().enumerate() {
+        require!(
+            lock_tier.lock_duration > 0
+                && lock_tier.reward_multiplier_bps as u64 >= BASIS_POINTS
+                && lock_tiers[..i]
+                    .iter()
+                    .all(|other| other.lock_duration != lock_tier.lock_duration),
+            ConfigErrors::InvalidLockTier
+        );
+    }
+
+    ctx.accounts.protocol_config.lock_tiers = lock_tiers.clone();
+
+    emit!(LockTiersUpdated { lock_tiers });
+
+    Ok(())
+}
+
+/// Whitelists `fee_mint` for fingerprint fees, or updates its fees if already listed.
+/// A mint without a fee pool needs one of the `MAX_FEE_MINTS` pool slots.
+pub fn set_fee_mint(ctx: Context<SetFeeMint>, fingerprint_fee: u64, batch_fee: u64) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+    let mint = ctx.accounts.fee_mint.key();
+    ctx.accounts.fundraising_account.token_pool(mint)?;
+
+    match protocol_config
+        .fee_mints
+        .iter_mut()
+        .find(|fee_mint| fee_mint.mint == mint)
+    {
+        Some(fee_mint) => {
+            fee_mint.fingerprint_fee = fingerprint_fee;
+            fee_mint.batch_fee = batch_fee;
+        }
+        None => {
+            require!(
+                protocol_config.fee_mints.len() < MAX_FEE_MINTS,
+                ConfigErrors::TooManyFeeMints
+            );
+            protocol_config.fee_mints.push(FeeMint {
+                mint,
+                fingerprint_fee,
+                batch_fee,
+            });
+        }
+    }
+
+    emit!(FeeMintUpdated {
+        mint,
+        fingerprint_fee,
+        batch_fee,
+        fee_vault: ctx.accounts.fee_vault.key(),
+    });
+
+    Ok(())
+}
+
+/// Stops accepting `mint` for fees. Its pool and vault are kept, so stakers
+/// can still claim what they earned in it, and adding the mint back resumes
+/// the same pool.
+pub fn remove_fee_mint(ctx: Context<RemoveFeeMint>, mint: Pubkey) -> Result<()> {
+    let protocol_config = &mut ctx.accounts.protocol_config;
+
+    let index = protocol_config
+        .fee_mints
+        .iter()
+        .position(|fee_mint| fee_mint.mint == mint)
+        .ok_or(ConfigErrors::FeeMintNotWhitelisted)?;
+    protocol_config.fee_mints.remove(index);
+
+    emit!(FeeMintRemoved { mint });
+
+    Ok(())
+}
+
+#[event]
+pub struct ProtocolConfigUpdated {
+    pub admin: Pubkey,
+    pub fee_schedule: FeeSchedule,
+}
+
+#[event]
+pub struct UnstakeCooldownUpdated {
+    pub unstake_cooldown: i64,
+}
+
+#[event]
+pub struct LockTiersUpdated {
+    pub lock_tiers: Vec<LockTier>,
+}
+
+#[event]
+pub struct FeeMintUpdated {
+    pub mint: Pubkey,
+    pub fingerprint_fee: u64,
+    pub batch_fee: u64,
+    pub fee_vault: Pubkey,
+}
+
+#[event]
+pub struct FeeMintRemoved {
+    pub mint: Pubkey,
+}
+
+#[error_code(offset = 6100)]
+pub enum ConfigErrors {
+    InvalidBasisPoints,
+    FeeOverflow,
+    FeeMintNotWhitelisted,
+    TooManyFeeMints,
+    InvalidUnstakeCooldown,
+    TooManyLockTiers,
+    InvalidLockTier,
+    LockTierNotFound,
+    NotUpgradeAuthority,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fee_schedule(volume_discount_threshold: u32, volume_discount_bps: u16) -> FeeSchedule {
+        FeeSchedule {
+            fingerprint_fee: 1_000,
+            batch_fee: 500,
+            data_account_fee: 0,
+            observer_approval_fee: 0,
+            volume_discount_threshold,
+            volume_discount_bps,
+            credit_price: 0,
+        }
+    }
+
+    #[test]
+    fn fee_for_charges_per_fingerprint_plus_flat_fee() {
+        let fee_schedule = fee_schedule(0, 5_000);
+
+        // A threshold of 0 disables the discount whatever the bps
+        assert_eq!(fee_schedule.fee_for(1_000, 500, 10).unwrap(), 10_500);
+        assert_eq!(fee_schedule.fee_for(1_000, 0, 1).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn fee_for_discounts_only_batches_at_the_threshold() {
+        let fee_schedule = fee_schedule(4, 2_500);
+
+        assert_eq!(fee_schedule.fee_for(1_000, 500, 3).unwrap(), 3_500);
+        assert_eq!(fee_schedule.fee_for(1_000, 500, 4).unwrap(), 3_500);
+        assert_eq!(fee_schedule.fee_for(1_000, 500, 8).unwrap(), 6_500);
+    }
+
+    #[test]
+    fn fee_for_rejects_overflow() {
+        let fee_schedule = fee_schedule(0, 0);
+
+        assert!(fee_schedule.fee_for(u64::MAX, 0, 2).is_err());
+        assert!(fee_schedule.fee_for(u64::MAX, 1, 1).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_discount_above_100_percent() {
+        assert!(fee_schedule(1, 10_000).validate().is_ok());
+        assert!(fee_schedule(1, 10_001).validate().is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::{self, system_instruction},
+};
+
+use crate::fundraising::FundraisingAccount;
+
+/// Lamports pre-funded by ship management to pay the fingerprint fees of its
+/// fleet, so the ships' keys only have to sign.
+#[account]
+pub struct FeeEscrow {
+    pub ship_management: Pubkey,
+    pub low_balance_threshold: u64,
+}
+
+impl FeeEscrow {
+    pub const SIZE: usize = 32 + 8;
+}
+
+/// Lamports available for fees, above the rent-exempt minimum.
+fn available_balance(fee_escrow: &Account<FeeEscrow>) -> Result<u64> {
+    let account_info = fee_escrow.to_account_info();
+    let rent_exempt_minimum = Rent::get()?.minimum_balance(account_info.data_len());
+    Ok(account_info.lamports().saturating_sub(rent_exempt_minimum))
+}
+
+#[derive(Accounts)]
+pub struct InitializeFeeEscrow<'info> {
+    #[account(mut)]
+    pub ship_management: Signer<'info>,
+    #[account(
+        init,
+        seeds = [b"fee_escrow", ship_management.key().as_ref()],
+        bump,
+        payer = ship_management,
+        space = 8 + FeeEscrow::SIZE
+    )]
+    pub fee_escrow: Account<'info, FeeEscrow>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeEscrow<'info> {
+    #[account(mut)]
+    pub ship_management: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"fee_escrow", ship_management.key().as_ref()],
+        bump,
+        has_one = ship_management
+    )]
+    pub fee_escrow: Account<'info, FeeEscrow>,
+    pub system_program: Program<'info, System>,
+}
+
+pub fn initialize_fee_escrow(
+    ctx: Context<InitializeFeeEscrow>,
+    low_balance_threshold: u64,
+) -> Result<()> {
+    let fee_escrow = &mut ctx.accounts.fee_escrow;
+    fee_escrow.ship_management = ctx.accounts.ship_management.key();
+    fee_escrow.low_balance_threshold = low_balance_threshold;
+
+    Ok(())
+}
+
+pub fn set_fee_escrow_threshold(
+    ctx: Context<UpdateFeeEscrow>,
+    low_balance_threshold: u64,
+) -> Result<()> {
+    ctx.accounts.fee_escrow.low_balance_threshold = low_balance_threshold;
+
+    Ok(())
+}
+
+pub fn top_up_fee_escrow(ctx: Context<UpdateFeeEscrow>, amount: u64) -> Result<()> {
+    let fee_escrow = &ctx.accounts.fee_escrow;
+
+    let transfer_instruction = system_instruction::transfer(
+        ctx.accounts.ship_management.key,
+        &fee_escrow.key(),
+        amount,
+    );
+
+    solana_program::program::invoke(
+        &transfer_instruction,
+        &[
+            ctx.accounts.ship_management.to_account_info(),
+            fee_escrow.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        ],
+    )?;
+
+    emit!(FeeEscrowToppedUp {
+        ship_management: fee_escrow.ship_management,
+        amount,
+        balance: available_balance(fee_escrow)?,
+    });
+
+    Ok(())
+}
+
+pub fn withdraw_fee_escrow(ctx: Context<UpdateFeeEscrow>, amount: u64) -> Result<()> {
+    let fee_escrow = &ctx.accounts.fee_escrow;
+    require!(
+        amount <= available_balance(fee_escrow)?,
+        EscrowErrors::InsufficientEscrowBalance
+    );
+
+    **fee_escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **ctx
+        .accounts
+        .ship_management
+        .to_account_info()
+        .try_borrow_mut_lamports()? += amount;
+
+    emit!(FeeEscrowWithdrawn {
+        ship_management: fee_escrow.ship_management,
+        amount,
+        balance: available_balance(fee_escrow)?,
+    });
+
+    Ok(())
+}
+
+/// Moves `amount` lamports from the escrow into the fundraising account and
+/// books them as protocol fees, warning management once the balance runs low.
+pub fn collect_fee_from_escrow<'info>(
+    fee_escrow: &Account<'info, FeeEscrow>,
+    fundraising_account: &mut Account<'info, FundraisingAccount>,
+    amount: u64,
+) -> Result<()> {
+    let available = available_balance(fee_escrow)?;
+    require!(amount <= available, EscrowErrors::InsufficientEscrowBalance);
+
+    **fee_escrow.to_account_info().try_borrow_mut_lamports()? -= amount;
+    **fundraising_account
+        .to_account_info()
+        .try_borrow_mut_lamports()? += amount;
+    fundraising_account.distribute_fees(amount)?;
+
+    let balance = available - amount;
+    if balance < fee_escrow.low_balance_threshold {
+        emit!(FeeEscrowLowBalance {
+            ship_management: fee_escrow.ship_management,
+            balance,
+            low_balance_threshold: fee_escrow.low_balance_threshold,
+        });
+    }
+
+    Ok(())
+}
+
+#[event]
+pub struct FeeEscrowToppedUp {
+    pub ship_management: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct FeeEscrowWithdrawn {
+    pub ship_management: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct FeeEscrowLowBalance {
+    pub ship_management: Pubkey,
+    pub balance: u64,
+    pub low_balance_threshold: u64,
+}
+
+#[error_code(offset = 6300)]
+pub enum EscrowErrors {
+    InsufficientEscrowBalance,
+    EscrowNotForShip,
+}
@@ -0,0 +1,81 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::{self, system_instruction},
+};
+use anchor_spl::{
+    associated_token::get_associated_token_address,
+    token::{transfer, Token, TokenAccount, Transfer},
+};
+
+use crate::fundraising::FundraisingAccount;
+
+/// Transfers `amount` lamports from `payer` into the fundraising account and
+/// books them as protocol fees for stakers.
+pub fn collect_fee<'info>(
+    payer: &AccountInfo<'info>,
+    fundraising_account: &mut Account<'info, FundraisingAccount>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let transfer_instruction =
+        system_instruction::transfer(payer.key, &fundraising_account.key(), amount);
+
+    solana_program::program::invoke(
+        &transfer_instruction,
+        &[
+            payer.clone(),
+            fundraising_account.to_account_info(),
+            system_program.clone(),
+        ],
+    )?;
+
+    fundraising_account.distribute_fees(amount)?;
+
+    Ok(())
+}
+
+/// Transfers `amount` of `payer_token_account`'s mint into the fundraising
+/// account's vault for that mint and books them as protocol fees for stakers.
+pub fn collect_token_fee<'info>(
+    payer: &Signer<'info>,
+    payer_token_account: &Account<'info, TokenAccount>,
+    fee_vault: &Account<'info, TokenAccount>,
+    fundraising_account: &mut Account<'info, FundraisingAccount>,
+    token_program: &Program<'info, Token>,
+    amount: u64,
+) -> Result<()> {
+    let mint = payer_token_account.mint;
+    require_keys_eq!(
+        fee_vault.key(),
+        get_associated_token_address(&fundraising_account.key(), &mint),
+        FeeErrors::InvalidFeeVault
+    );
+
+    if amount == 0 {
+        return Ok(());
+    }
+
+    transfer(
+        CpiContext::new(
+            token_program.to_account_info(),
+            Transfer {
+                from: payer_token_account.to_account_info(),
+                to: fee_vault.to_account_info(),
+                authority: payer.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    fundraising_account.record_token_fee(mint, amount)
+}
+
+#[error_code(offset = 6200)]
+pub enum FeeErrors {
+    InvalidFeeVault,
+    FeeTokenAccountsMissing,
+}
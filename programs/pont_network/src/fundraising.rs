@@ -1,6 +1,7 @@
 use anchor_lang::{
     prelude::*,
-    solana_program::{self, instruction::Instruction, program::{invoke, invoke_signed}, system_instruction},
+    solana_program::{self, system_instruction},
+    system_program, Discriminator,
 };
 use anchor_spl::{
     associated_token::AssociatedToken,
@@ -8,40 +9,522 @@ use anchor_spl::{
         create_metadata_accounts_v3, mpl_token_metadata::types::DataV2, CreateMetadataAccountsV3,
         Metadata,
     },
-    token::{mint_to, transfer, Mint, MintTo, Token, TokenAccount, Transfer},
+    token::{
+        burn, close_account, mint_to, transfer, Burn, CloseAccount, Mint, MintTo, Token,
+        TokenAccount, Transfer,
+    },
 };
 
-use crate::{__private::__global::claim_rewards, id, instruction};
+use crate::config::{ProtocolConfig, BASIS_POINTS, MAX_FEE_MINTS};
+use crate::ANCHOR_DISCRIMINATOR;
+
+// Scale of `reward_per_token`, in lamports per staked base unit
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000_000_000;
+// One whole share. Tiny stakes would inflate `reward_per_token` by up to a
+// fee per base unit on their own, so stakes are either empty or at least this.
+pub const MIN_STAKE: u64 = 1_000_000_000;
 
 #[account]
 pub struct FundraisingAccount {
+    pub token_mint: Pubkey,
+    // Rounds started so far, the next round's index
+    pub round_count: u64,
+    pub last_round_end_time: i64,
+    pub total_fees_collected: u64,
+    // Fees paid in whitelisted SPL mints, held in the vaults owned by this account
+    pub token_fees_collected: Vec<TokenFees>,
+    // Id of the next entry added to token_fees_collected
+    pub next_token_pool_id: u64,
+    // Sum of every staker's weighted stake, see UserAccount::weighted_stake
+    pub total_staked: u64,
+    // Lamport fees earned per weighted staked token since the start, scaled by REWARD_PRECISION
+    pub reward_per_token: u128,
+    // Fees collected while nothing was staked, handed out with the next fee
+    pub undistributed_fees: u64,
+}
+
+impl FundraisingAccount {
+    // Stakes live in their own PDAs, so this is fixed apart from the fee mints
+    pub const SIZE: usize = 32 + 8 + 8 + 8 + 4 + MAX_FEE_MINTS * TokenFees::SIZE + 8 + 8 + 16 + 8;
+
+    /// Changes a settled user's stake and lock multiplier, keeping `total_staked` in sync.
+    pub fn set_user_stake(
+        &mut self,
+        user_account: &mut UserAccount,
+        amount_staked: u64,
+        reward_multiplier_bps: u16,
+    ) -> Result<()> {
+        self.total_staked -= user_account.weighted_stake;
+        user_account.set_stake(amount_staked, reward_multiplier_bps, self)?;
+        self.total_staked = self
+            .total_staked
+            .checked_add(user_account.weighted_stake)
+            .ok_or(FundraisingErrors::StakeOverflow)?;
+
+        Ok(())
+    }
+
+    /// Drops a settled user's lock boost back to 1x once the lock has expired.
+    /// Returns whether there was a boost to drop; a fresh account (0 bps) has none.
+    pub fn end_expired_boost(&mut self, user_account: &mut UserAccount, now: i64) -> Result<bool> {
+        if now < user_account.lock_until
+            || user_account.reward_multiplier_bps <= BASIS_POINTS as u16
+        {
+            return Ok(false);
+        }
+
+        let amount_staked = user_account.amount_staked;
+        self.set_user_stake(user_account, amount_staked, BASIS_POINTS as u16)?;
+
+        emit!(LockBoostEnded {
+            user: user_account.key,
+            lock_until: user_account.lock_until,
+            weighted_stake: user_account.weighted_stake,
+        });
+
+        Ok(true)
+    }
+
+    /// Books `amount` lamports of fees and credits them to current stakers.
+    pub fn distribute_fees(&mut self, amount: u64) -> Result<()> {
+        self.total_fees_collected += amount;
+        distribute(
+            &mut self.reward_per_token,
+            &mut self.undistributed_fees,
+            amount,
+            self.total_staked,
+        )
+    }
+
+    pub fn token_fees(&self, mint: &Pubkey) -> u64 {
+        self.token_fees_for(mint)
+            .map_or(0, |token_fees| token_fees.amount)
+    }
+
+    pub fn token_fees_for(&self, mint: &Pubkey) -> Option<&TokenFees> {
+        self.token_fees_collected
+            .iter()
+            .find(|token_fees| token_fees.mint == *mint)
+    }
+
+    /// Index of `mint`'s fee pool, adding the pool if the mint has none yet.
+    /// Pools are never removed, so rewards stay claimable after a mint leaves
+    /// the whitelist and the pool resumes if the mint is added back.
+    pub fn token_pool(&mut self, mint: Pubkey) -> Result<usize> {
+        if let Some(index) = self
+            .token_fees_collected
+            .iter()
+            .position(|token_fees| token_fees.mint == mint)
+        {
+            return Ok(index);
+        }
+
+        require!(
+            self.token_fees_collected.len() < MAX_FEE_MINTS,
+            FundraisingErrors::TooManyFeeMints
+        );
+        self.token_fees_collected.push(TokenFees {
+            mint,
+            pool_id: self.next_token_pool_id,
+            amount: 0,
+            reward_per_token: 0,
+            undistributed_fees: 0,
+        });
+        self.next_token_pool_id += 1;
+
+        Ok(self.token_fees_collected.len() - 1)
+    }
+
+    /// Books `amount` of `mint` as fees and credits them to current stakers.
+    pub fn record_token_fee(&mut self, mint: Pubkey, amount: u64) -> Result<()> {
+        let index = self.token_pool(mint)?;
+        let total_staked = self.total_staked;
+        let token_fees = &mut self.token_fees_collected[index];
+        token_fees.amount += amount;
+        distribute(
+            &mut token_fees.reward_per_token,
+            &mut token_fees.undistributed_fees,
+            amount,
+            total_staked,
+        )
+    }
+}
+
+/// Credits `amount` of fees, plus any held back earlier, to `total_staked`.
+/// With nothing staked they are held back for the next fee.
+fn distribute(
+    reward_per_token: &mut u128,
+    undistributed_fees: &mut u64,
+    amount: u64,
+    total_staked: u64,
+) -> Result<()> {
+    let fees = undistributed_fees
+        .checked_add(amount)
+        .ok_or(FundraisingErrors::RewardOverflow)?;
+    if total_staked == 0 {
+        *undistributed_fees = fees;
+        return Ok(());
+    }
+
+    // Below 2^64 * 10^18, which fits in a u128
+    let increase = fees as u128 * REWARD_PRECISION / total_staked as u128;
+    *reward_per_token = reward_per_token
+        .checked_add(increase)
+        .ok_or(FundraisingErrors::RewardOverflow)?;
+    *undistributed_fees = 0;
+
+    Ok(())
+}
+
+/// Weighted stake's share of everything credited at `reward_per_token`.
+///
+/// `weighted_stake * reward_per_token` can exceed a u128 even when the result
+/// does not, so the whole and fractional parts of `reward_per_token` are
+/// multiplied separately. Exact, and fails only if the result itself overflows.
+fn accrued(weighted_stake: u64, reward_per_token: u128) -> Result<u128> {
+    let weighted_stake = weighted_stake as u128;
+    let whole = reward_per_token / REWARD_PRECISION;
+    let fraction = reward_per_token % REWARD_PRECISION;
+
+    weighted_stake
+        .checked_mul(whole)
+        .and_then(|rewards| rewards.checked_add(weighted_stake * fraction / REWARD_PRECISION))
+        .ok_or_else(|| error!(FundraisingErrors::RewardOverflow))
+}
+
+/// Rewards accrued between two settlements, added to what is still unclaimed.
+fn add_rewards(unclaimed_rewards: u64, accrued_rewards: u128, reward_debt: u128) -> Result<u64> {
+    u64::try_from(accrued_rewards - reward_debt)
+        .ok()
+        .and_then(|rewards| unclaimed_rewards.checked_add(rewards))
+        .ok_or_else(|| error!(FundraisingErrors::RewardOverflow))
+}
+
+/// A single raise selling shares of the common mint. Contributions stay in
+/// this account, apart from the protocol fees held by `FundraisingAccount`.
+#[account]
+pub struct FundraisingRound {
+    pub index: u64,
+    pub kind: RoundKind,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub total_funds_raised: u64,
+    // Lamports
+    pub hard_cap: u64,
+    pub soft_cap: u64,
+    // Lamports per whole share token
+    pub price_per_token: u64,
+    // Shares vest linearly over `vesting_duration` from the end of the round,
+    // nothing unlocks before `cliff_duration` has passed
+    pub cliff_duration: i64,
+    pub vesting_duration: i64,
+}
+
+impl FundraisingRound {
+    pub const SIZE: usize = 8 + 1 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8;
+
+    /// Whether the round ended below its soft cap, making contributions refundable.
+    pub fn failed(&self, now: i64) -> bool {
+        now > self.end_time && self.total_funds_raised < self.soft_cap
+    }
+
+    /// Whether the round ended at or above its soft cap, releasing the raised funds.
+    pub fn succeeded(&self, now: i64) -> bool {
+        now > self.end_time && self.total_funds_raised >= self.soft_cap
+    }
+}
+
+/// What a user put into a round, kept so it can be refunded if the round fails.
+/// The shares bought are held in the contribution's vesting vault until released.
+#[account]
+pub struct Contribution {
+    pub round: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub tokens: u64,
+    pub released: u64,
+}
+
+impl Contribution {
+    pub const SIZE: usize = 32 + 32 + 8 + 8 + 8;
+
+    /// Shares unlocked at `now` under the round's vesting schedule.
+    pub fn vested(&self, fundraising_round: &FundraisingRound, now: i64) -> u64 {
+        let elapsed = now - fundraising_round.end_time;
+        if elapsed < fundraising_round.cliff_duration {
+            return 0;
+        }
+        if elapsed >= fundraising_round.vesting_duration {
+            return self.tokens;
+        }
+
+        (self.tokens as u128 * elapsed as u128 / fundraising_round.vesting_duration as u128) as u64
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundKind {
+    Seed,
+    Private,
+    Public,
+}
+
+/// Fees collected in one SPL mint, shared by stakers like lamport fees.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TokenFees {
+    pub mint: Pubkey,
+    // Matches UserAccount::token_rewards entries
+    pub pool_id: u64,
+    pub amount: u64,
+    // Fees earned per weighted staked token since the pool was added, scaled by REWARD_PRECISION
+    pub reward_per_token: u128,
+    // Fees collected while nothing was staked, handed out with the next fee
+    pub undistributed_fees: u64,
+}
+
+impl TokenFees {
+    pub const SIZE: usize = 32 + 8 + 8 + 16 + 8;
+}
+
+/// A staker's settlement state for one `TokenFees` pool.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TokenRewards {
+    pub pool_id: u64,
+    // weighted_stake * the pool's reward_per_token at the last settlement
+    pub reward_debt: u128,
+    // Settled rewards in the pool's mint not yet paid out
+    pub unclaimed_rewards: u64,
+}
+
+impl TokenRewards {
+    pub const SIZE: usize = 8 + 16 + 8;
+}
+
+#[account]
+pub struct UserAccount {
+    pub key: Pubkey,
+    pub amount_staked: u64,
+    // Multiplier of the lock tier the stake was made with, 10_000 is 1x
+    pub reward_multiplier_bps: u16,
+    // amount_staked * reward_multiplier_bps, the stake rewards are shared by
+    pub weighted_stake: u64,
+    // The stake cannot be unstaked before this time
+    pub lock_until: i64,
+    // weighted_stake * reward_per_token at the last settlement, already accounted for
+    pub reward_debt: u128,
+    // Settled lamport rewards not yet paid out
+    pub unclaimed_rewards: u64,
+    // last claim slot
+    pub last_claimed_fees_slot: u64,
+    // pub total_fees_claimed: u64,
+    // Settlement state per token fee pool, in the order of token_fees_collected
+    pub token_rewards: Vec<TokenRewards>,
+}
+
+impl UserAccount {
+    // Stored in its own PDA, seeded by the staker's key
+    pub const SIZE: usize =
+        32 + 8 + 2 + 8 + 8 + 16 + 8 + 8 + 4 + MAX_FEE_MINTS * TokenRewards::SIZE;
+
+    /// Moves lamport and token rewards accrued since the last settlement into
+    /// the unclaimed balances.
+    pub fn settle(&mut self, fundraising_account: &FundraisingAccount) -> Result<()> {
+        let accrued_rewards = accrued(self.weighted_stake, fundraising_account.reward_per_token)?;
+        self.unclaimed_rewards =
+            add_rewards(self.unclaimed_rewards, accrued_rewards, self.reward_debt)?;
+        self.reward_debt = accrued_rewards;
+
+        let token_rewards = fundraising_account
+            .token_fees_collected
+            .iter()
+            .map(|token_fees| {
+                let accrued_rewards = accrued(self.weighted_stake, token_fees.reward_per_token)?;
+                // Every stake change settles all pools, so a pool without an
+                // entry was added after the stake last changed
+                let (reward_debt, unclaimed_rewards) = self
+                    .token_rewards
+                    .iter()
+                    .find(|token_rewards| token_rewards.pool_id == token_fees.pool_id)
+                    .map_or((0, 0), |token_rewards| {
+                        (token_rewards.reward_debt, token_rewards.unclaimed_rewards)
+                    });
+
+                Ok(TokenRewards {
+                    pool_id: token_fees.pool_id,
+                    reward_debt: accrued_rewards,
+                    unclaimed_rewards: add_rewards(
+                        unclaimed_rewards,
+                        accrued_rewards,
+                        reward_debt,
+                    )?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        self.token_rewards = token_rewards;
+
+        Ok(())
+    }
+
+    /// Takes the settled rewards of token pool `pool_id` for payout.
+    pub fn take_token_rewards(&mut self, pool_id: u64) -> u64 {
+        self.token_rewards
+            .iter_mut()
+            .find(|token_rewards| token_rewards.pool_id == pool_id)
+            .map_or(0, |token_rewards| {
+                std::mem::take(&mut token_rewards.unclaimed_rewards)
+            })
+    }
+
+    /// Changes the stake of a settled user without crediting past rewards to it.
+    /// Use `FundraisingAccount::set_user_stake` so `total_staked` follows.
+    fn set_stake(
+        &mut self,
+        amount_staked: u64,
+        reward_multiplier_bps: u16,
+        fundraising_account: &FundraisingAccount,
+    ) -> Result<()> {
+        self.weighted_stake = u64::try_from(
+            amount_staked as u128 * reward_multiplier_bps as u128 / BASIS_POINTS as u128,
+        )
+        .map_err(|_| error!(FundraisingErrors::StakeOverflow))?;
+        self.amount_staked = amount_staked;
+        self.reward_multiplier_bps = reward_multiplier_bps;
+        self.reward_debt = accrued(self.weighted_stake, fundraising_account.reward_per_token)?;
+
+        for (token_rewards, token_fees) in self
+            .token_rewards
+            .iter_mut()
+            .zip(&fundraising_account.token_fees_collected)
+        {
+            token_rewards.reward_debt = accrued(self.weighted_stake, token_fees.reward_per_token)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Shares a user has stopped staking, claimable once `unlock_time` has passed.
+/// Further requests add to the amount and restart the cooldown.
+#[account]
+pub struct UnstakeRequest {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub unlock_time: i64,
+}
+
+impl UnstakeRequest {
+    pub const SIZE: usize = 32 + 8 + 8;
+}
+
+/// `FundraisingAccount` as first deployed, with every stake kept in a vector
+/// inside it. Only read by `migrate_fundraising_account`.
+#[derive(AnchorDeserialize, Clone, Debug)]
+pub struct LegacyFundraisingAccount {
     pub start_time: i64,
     pub end_time: i64,
     pub total_funds_raised: u64,
     pub token_mint: Pubkey,
     pub total_fees_collected: u64,
     pub total_staked: u64,
-    pub user_staking_info: Vec<UserAccount>,
+    pub user_staking_info: Vec<LegacyUserAccount>,
 }
 
-#[account]
-pub struct UserAccount {
+/// Entry of `LegacyFundraisingAccount::user_staking_info`.
+#[derive(AnchorDeserialize, Clone, Debug)]
+pub struct LegacyUserAccount {
     pub key: Pubkey,
     pub amount_staked: u64,
     // Total fees collected by protocol when user last claimed
     pub total_fees_when_last_claimed: u64,
-    // last claim slot
     pub last_claimed_fees_slot: u64,
-    // pub total_fees_claimed: u64,
+}
+
+impl LegacyFundraisingAccount {
+    // The legacy account was allocated with a fixed 1000 bytes for its vector
+    pub const SPACE: usize = 8 + 8 + 8 + 8 + 32 + 1000;
+
+    /// Decodes the raw data of a fundraising account still in the legacy layout.
+    pub fn try_from_bytes(data: &[u8]) -> Result<Self> {
+        require!(
+            data.len() == Self::SPACE && data.starts_with(&FundraisingAccount::discriminator()),
+            FundraisingErrors::NotLegacyFundraisingAccount
+        );
+
+        Self::deserialize(&mut &data[ANCHOR_DISCRIMINATOR..])
+            .map_err(|_| error!(FundraisingErrors::NotLegacyFundraisingAccount))
+    }
+
+    /// The account in the current layout, and a 1x stake for every legacy entry.
+    /// Fees an entry earned since its last claim become its unclaimed rewards.
+    pub fn migrate(&self) -> Result<(FundraisingAccount, Vec<UserAccount>)> {
+        let total_staked = self
+            .user_staking_info
+            .iter()
+            .try_fold(0u64, |total, user| total.checked_add(user.amount_staked))
+            .ok_or(FundraisingErrors::StakeOverflow)?;
+        // Fees were split over the recorded total, never less than the entries hold
+        let reward_shares = self.total_staked.max(total_staked);
+
+        let user_accounts = self
+            .user_staking_info
+            .iter()
+            .map(|user| {
+                let fees_since_last_claim = self
+                    .total_fees_collected
+                    .saturating_sub(user.total_fees_when_last_claimed);
+                let unclaimed_rewards = if reward_shares == 0 {
+                    0
+                } else {
+                    (fees_since_last_claim as u128 * user.amount_staked as u128
+                        / reward_shares as u128) as u64
+                };
+
+                UserAccount {
+                    key: user.key,
+                    amount_staked: user.amount_staked,
+                    reward_multiplier_bps: BASIS_POINTS as u16,
+                    weighted_stake: user.amount_staked,
+                    lock_until: 0,
+                    reward_debt: 0,
+                    unclaimed_rewards,
+                    last_claimed_fees_slot: user.last_claimed_fees_slot,
+                    token_rewards: vec![],
+                }
+            })
+            .collect();
+
+        let fundraising_account = FundraisingAccount {
+            token_mint: self.token_mint,
+            round_count: 0,
+            last_round_end_time: self.end_time,
+            total_fees_collected: self.total_fees_collected,
+            token_fees_collected: vec![],
+            next_token_pool_id: 0,
+            total_staked,
+            reward_per_token: 0,
+            undistributed_fees: 0,
+        };
+
+        Ok((fundraising_account, user_accounts))
+    }
 }
 
 #[derive(Accounts)]
 pub struct Stake<'info> {
     #[account(mut)]
     pub sender: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     #[account(mut, seeds = [b"fundraising"], bump)]
     pub fundraising_account: Account<'info, FundraisingAccount>,
-    pub recipient: SystemAccount<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [b"user_stake", sender.key().as_ref()],
+        bump,
+        payer = sender,
+        space = 8 + UserAccount::SIZE
+    )]
+    pub user_stake: Account<'info, UserAccount>,
 
     #[account(mut, seeds = [b"mint"], bump)]
     pub mint_account: Account<'info, Mint>,
@@ -51,11 +534,12 @@ pub struct Stake<'info> {
         associated_token::authority = sender,
     )]
     pub sender_token_account: Account<'info, TokenAccount>,
+    // Staked shares are held by the fundraising account, `unstake` pays out from here
     #[account(
         init_if_needed,
         payer = sender,
         associated_token::mint = mint_account,
-        associated_token::authority = recipient,
+        associated_token::authority = fundraising_account,
     )]
     pub recipient_token_account: Account<'info, TokenAccount>,
 
@@ -65,12 +549,41 @@ pub struct Stake<'info> {
 }
 
 #[derive(Accounts)]
-pub struct Unstake<'info> {
+pub struct RequestUnstake<'info> {
     #[account(mut)]
-    pub recipient: Signer<'info>,
+    pub user: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     #[account(mut, seeds = [b"fundraising"], bump)]
     pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(mut, seeds = [b"user_stake", user.key().as_ref()], bump)]
+    pub user_stake: Account<'info, UserAccount>,
+    #[account(
+        init_if_needed,
+        seeds = [b"unstake_request", user.key().as_ref()],
+        bump,
+        payer = user,
+        space = 8 + UnstakeRequest::SIZE
+    )]
+    pub unstake_request: Account<'info, UnstakeRequest>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawUnstaked<'info> {
     #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(
+        mut,
+        seeds = [b"unstake_request", user.key().as_ref()],
+        bump,
+        has_one = user,
+        close = user
+    )]
+    pub unstake_request: Account<'info, UnstakeRequest>,
+    #[account(seeds = [b"mint"], bump)]
     pub mint_account: Account<'info, Mint>,
     #[account(
         mut,
@@ -80,9 +593,9 @@ pub struct Unstake<'info> {
     pub sender_token_account: Account<'info, TokenAccount>,
     #[account(
         init_if_needed,
-        payer = recipient,
+        payer = user,
         associated_token::mint = mint_account,
-        associated_token::authority = recipient,
+        associated_token::authority = user,
     )]
     pub recipient_token_account: Account<'info, TokenAccount>,
 
@@ -92,16 +605,158 @@ pub struct Unstake<'info> {
 }
 
 #[derive(Accounts)]
-pub struct StartFundraising<'info> {
+pub struct Refund<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"fundraising_round", fundraising_round.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"contribution", fundraising_round.key().as_ref(), user.key().as_ref()],
+        bump,
+        has_one = user
+    )]
+    pub contribution: Account<'info, Contribution>,
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = contribution,
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    #[account(mut, seeds = [b"mint"], bump)]
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseVested<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"fundraising_round", fundraising_round.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    #[account(
+        mut,
+        seeds = [b"contribution", fundraising_round.key().as_ref(), user.key().as_ref()],
+        bump,
+        has_one = user
+    )]
+    pub contribution: Account<'info, Contribution>,
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = contribution,
+    )]
+    pub vesting_vault: Account<'info, TokenAccount>,
+    // Released shares land in the same account `stake` draws from
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(seeds = [b"mint"], bump)]
+    pub token_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(mut, seeds = [b"user_stake", user.key().as_ref()], bump)]
+    pub user_stake: Account<'info, UserAccount>,
+    #[account(mut, associated_token::mint = token_mint, associated_token::authority = user)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub token_mint: Account<'info, Mint>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateFundraisingAccount<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: Still in the legacy layout, which `Account` cannot decode. The
+    /// address is checked by the seeds, the size and discriminator when decoding.
+    #[account(mut, seeds = [b"fundraising"], bump, owner = crate::ID)]
+    pub fundraising_account: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    // Remaining accounts: the `user_stake` PDA of every legacy entry, in order
+}
+
+#[derive(Accounts)]
+pub struct ExpireStakeLock<'info> {
+    pub caller: Signer<'info>,
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(mut, seeds = [b"user_stake", user_stake.key.as_ref()], bump)]
+    pub user_stake: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTokenRewards<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
-    #[account(init, seeds = [b"fundraising"], bump, payer = user, space = 8 + 8 + 8 + 8 + 32 + 1000)]
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(mut, seeds = [b"user_stake", user.key().as_ref()], bump)]
+    pub user_stake: Account<'info, UserAccount>,
+    pub fee_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = fee_mint,
+        associated_token::authority = fundraising_account,
+    )]
+    pub fee_vault: Account<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = fee_mint,
+        associated_token::authority = user,
+    )]
+    pub user_fee_token_account: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeFundraising<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        init,
+        seeds = [b"fundraising"],
+        bump,
+        payer = admin,
+        space = 8 + FundraisingAccount::SIZE
+    )]
     pub fundraising_account: Account<'info, FundraisingAccount>,
     #[account(
         init,
         seeds = [b"mint"],
         bump,
-        payer = user,
+        payer = admin,
         mint::decimals = 9,
         mint::authority = mint_account.key(),
         mint::freeze_authority = mint_account.key(),
@@ -124,18 +779,49 @@ pub struct StartFundraising<'info> {
 }
 
 #[derive(Accounts)]
-pub struct Contribute<'info> {
+pub struct StartFundraisingRound<'info> {
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub admin: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     #[account(mut, seeds = [b"fundraising"], bump)]
     pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(
+        init,
+        seeds = [b"fundraising_round", fundraising_account.round_count.to_le_bytes().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + FundraisingRound::SIZE
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Contribute<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"fundraising_round", fundraising_round.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    #[account(
+        init_if_needed,
+        seeds = [b"contribution", fundraising_round.key().as_ref(), user.key().as_ref()],
+        bump,
+        payer = user,
+        space = 8 + Contribution::SIZE
+    )]
+    pub contribution: Account<'info, Contribution>,
     #[account(
         init_if_needed,
         payer = user,
         associated_token::mint = token_mint,
-        associated_token::authority = user,
+        associated_token::authority = contribution,
     )]
-    pub associated_token_account: Account<'info, TokenAccount>,
+    pub vesting_vault: Account<'info, TokenAccount>,
     #[account(
         mut,
         seeds = [b"mint"],
@@ -148,17 +834,23 @@ pub struct Contribute<'info> {
     pub system_program: Program<'info, System>,
 }
 
-pub fn start_fundraising(ctx: Context<StartFundraising>) -> Result<()> {
+pub fn initialize_fundraising(
+    ctx: Context<InitializeFundraising>,
+    name: String,
+    symbol: String,
+    uri: String,
+) -> Result<()> {
     let fundraising_account = &mut ctx.accounts.fundraising_account;
-    let clock = Clock::get()?;
-    fundraising_account.start_time = clock.unix_timestamp;
-    fundraising_account.end_time = clock.unix_timestamp + 7 * 24 * 60 * 60; // 7 days
-    fundraising_account.total_funds_raised = 0;
     fundraising_account.token_mint = ctx.accounts.mint_account.key();
+    fundraising_account.round_count = 0;
+    fundraising_account.last_round_end_time = 0;
     fundraising_account.total_fees_collected = 0;
-    fundraising_account.user_staking_info = vec![];
+    fundraising_account.token_fees_collected = vec![];
+    fundraising_account.next_token_pool_id = 0;
+    fundraising_account.reward_per_token = 0;
+    fundraising_account.undistributed_fees = 0;
 
-    msg!("Fundraising started");
+    msg!("Fundraising initialized");
 
     msg!("Creating metadata account...");
     msg!(
@@ -177,17 +869,17 @@ pub fn start_fundraising(ctx: Context<StartFundraising>) -> Result<()> {
                 metadata: ctx.accounts.metadata_account.to_account_info(),
                 mint: ctx.accounts.mint_account.to_account_info(),
                 mint_authority: ctx.accounts.mint_account.to_account_info(),
-                update_authority: ctx.accounts.user.to_account_info(),
-                payer: ctx.accounts.user.to_account_info(),
+                update_authority: ctx.accounts.admin.to_account_info(),
+                payer: ctx.accounts.admin.to_account_info(),
                 system_program: ctx.accounts.system_program.to_account_info(),
                 rent: ctx.accounts.rent.to_account_info(),
             },
         )
         .with_signer(signer_seeds),
         DataV2 {
-            name: "Pont Network Shares".to_string(),
-            symbol: "PNTSH".to_string(),
-            uri: "https://example.com".to_string(),
+            name,
+            symbol,
+            uri,
             seller_fee_basis_points: 0,
             creators: None,
             collection: None,
@@ -203,38 +895,117 @@ pub fn start_fundraising(ctx: Context<StartFundraising>) -> Result<()> {
     Ok(())
 }
 
-pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
-    let fundraising_account = &mut ctx.accounts.fundraising_account;
+/// Opens the next round. Rounds run one after another, so the previous
+/// round must have ended.
+#[allow(clippy::too_many_arguments)]
+pub fn start_fundraising_round(
+    ctx: Context<StartFundraisingRound>,
+    kind: RoundKind,
+    duration: i64,
+    hard_cap: u64,
+    soft_cap: u64,
+    price_per_token: u64,
+    cliff_duration: i64,
+    vesting_duration: i64,
+) -> Result<()> {
+    require!(
+        duration > 0 && price_per_token > 0 && soft_cap <= hard_cap,
+        FundraisingErrors::InvalidFundraisingParameters
+    );
+    require!(
+        0 <= cliff_duration && cliff_duration <= vesting_duration,
+        FundraisingErrors::InvalidVestingSchedule
+    );
+
+    let fundraising_account = &mut ctx.accounts.fundraising_account;
     let clock = Clock::get()?;
-    if clock.unix_timestamp > fundraising_account.end_time {
+    require!(
+        clock.unix_timestamp > fundraising_account.last_round_end_time,
+        FundraisingErrors::PreviousRoundActive
+    );
+
+    let fundraising_round = &mut ctx.accounts.fundraising_round;
+    fundraising_round.index = fundraising_account.round_count;
+    fundraising_round.kind = kind;
+    fundraising_round.start_time = clock.unix_timestamp;
+    fundraising_round.end_time = clock.unix_timestamp + duration;
+    fundraising_round.total_funds_raised = 0;
+    fundraising_round.hard_cap = hard_cap;
+    fundraising_round.soft_cap = soft_cap;
+    fundraising_round.price_per_token = price_per_token;
+    fundraising_round.cliff_duration = cliff_duration;
+    fundraising_round.vesting_duration = vesting_duration;
+
+    fundraising_account.round_count += 1;
+    fundraising_account.last_round_end_time = fundraising_round.end_time;
+
+    emit!(FundraisingRoundStarted {
+        round: fundraising_round.key(),
+        index: fundraising_round.index,
+        kind,
+        start_time: fundraising_round.start_time,
+        end_time: fundraising_round.end_time,
+        hard_cap,
+        soft_cap,
+        price_per_token,
+        cliff_duration,
+        vesting_duration,
+    });
+
+    Ok(())
+}
+
+pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
+    let fundraising_round = &mut ctx.accounts.fundraising_round;
+    let clock = Clock::get()?;
+    if clock.unix_timestamp > fundraising_round.end_time {
         return err!(FundraisingErrors::FundraisingPeriodEnded); // Fundraising period has ended
     }
+    let total_funds_raised = fundraising_round
+        .total_funds_raised
+        .checked_add(amount)
+        .filter(|total| *total <= fundraising_round.hard_cap)
+        .ok_or(FundraisingErrors::HardCapReached)?;
+
+    // Shares for `amount` lamports at the round price, in base units
+    let tokens = u64::try_from(
+        amount as u128 * 10u128.pow(ctx.accounts.token_mint.decimals as u32)
+            / fundraising_round.price_per_token as u128,
+    )
+    .map_err(|_| FundraisingErrors::ShareOverflow)?;
+    require!(tokens > 0, FundraisingErrors::ContributionTooSmall);
 
     let user = &mut ctx.accounts.user;
 
     // Create the system transfer instruction
     let transfer_instruction =
-        system_instruction::transfer(user.key, &fundraising_account.key(), amount);
+        system_instruction::transfer(user.key, &fundraising_round.key(), amount);
 
     // Send the transfer instruction
     solana_program::program::invoke(
         &transfer_instruction,
         &[
-            fundraising_account.to_account_info(),
+            fundraising_round.to_account_info(),
             ctx.accounts.user.to_account_info(),
             ctx.accounts.system_program.to_account_info(),
         ],
     )?;
 
-    fundraising_account.total_funds_raised += amount;
+    fundraising_round.total_funds_raised = total_funds_raised;
 
-    // Mint PONT SHARES tokens to the user
-    msg!("Minting token to associated token account...");
+    let contribution = &mut ctx.accounts.contribution;
+    contribution.round = fundraising_round.key();
+    contribution.user = ctx.accounts.user.key();
+    contribution.amount += amount;
+    contribution.tokens = contribution
+        .tokens
+        .checked_add(tokens)
+        .ok_or(FundraisingErrors::ShareOverflow)?;
+
+    // Mint PONT SHARES tokens into the contribution's vesting vault
+    msg!("Minting token to vesting vault...");
     msg!("Mint: {}", &ctx.accounts.token_mint.key());
-    msg!(
-        "Token Address: {}",
-        &ctx.accounts.associated_token_account.key()
-    );
+    msg!("Token Address: {}", &ctx.accounts.vesting_vault.key());
 
     // PDA signer seeds
     let signer_seeds: &[&[&[u8]]] = &[&[b"mint", &[ctx.bumps.token_mint]]];
@@ -245,50 +1016,125 @@ pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
             ctx.accounts.token_program.to_account_info(),
             MintTo {
                 mint: ctx.accounts.token_mint.to_account_info(),
-                to: ctx.accounts.associated_token_account.to_account_info(),
+                to: ctx.accounts.vesting_vault.to_account_info(),
                 authority: ctx.accounts.token_mint.to_account_info(), // PDA mint authority, required as signer
             },
         )
         .with_signer(signer_seeds), // using PDA to sign
-        amount, // Mint tokens, adjust for decimals
+        tokens,
     )?;
 
     msg!("User contributed {} lamports", amount);
     msg!(
-        "Minted {} tokens to user {}",
-        amount,
+        "Minted {} tokens vesting for user {}",
+        tokens,
         ctx.accounts.user.key()
     );
 
     Ok(())
 }
 
-pub fn stake_and_claim(ctx: Context<Stake>, amount: u64) -> Result<()> {
-    // let claim_rewards_ix = Instruction {
-    //     program_id: id(),
-    //     accounts: vec![
-    //         AccountMeta::new(*ctx.accounts.sender.key, true),
-    //         AccountMeta::new(ctx.accounts.fundraising_account.key(), false),
-    //         AccountMeta::new(ctx.accounts.sender_token_account.key(), false),
-    //         AccountMeta::new(ctx.accounts.mint_account.key(), false),
-    //         AccountMeta::new_readonly(ctx.accounts.system_program.key.key(), false),
-    //     ],
-    //     data: instruction::ClaimRewards.try_to_vec().unwrap(),
-    // };
-
-    // invoke(
-    //     &claim_rewards_ix,
-    //     &[
-    //         ctx.accounts.sender.to_account_info(),
-    //         ctx.accounts.fundraising_account.to_account_info(),
-    //         ctx.accounts.sender_token_account.to_account_info(),
-    //         ctx.accounts.mint_account.to_account_info(),
-    //         ctx.accounts.system_program.to_account_info(),
-    //     ],
-    // )?;
+/// Moves the shares unlocked so far from the vesting vault to the user.
+pub fn release_vested(ctx: Context<ReleaseVested>) -> Result<()> {
+    let fundraising_round = &ctx.accounts.fundraising_round;
+    let contribution = &mut ctx.accounts.contribution;
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now > fundraising_round.end_time,
+        FundraisingErrors::RoundNotEnded
+    );
+    require!(
+        fundraising_round.succeeded(now),
+        FundraisingErrors::RoundFailed
+    );
+
+    let amount = contribution.vested(fundraising_round, now) - contribution.released;
+    require!(amount > 0, FundraisingErrors::NothingVested);
+
+    let round_key = fundraising_round.key();
+    let user_key = ctx.accounts.user.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"contribution",
+        round_key.as_ref(),
+        user_key.as_ref(),
+        &[ctx.bumps.contribution],
+    ]];
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vesting_vault.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: contribution.to_account_info(),
+            },
+        )
+        .with_signer(signer_seeds),
+        amount,
+    )?;
+
+    contribution.released += amount;
+
+    emit!(SharesReleased {
+        round: round_key,
+        user: user_key,
+        amount,
+        released: contribution.released,
+        tokens: contribution.tokens,
+    });
+
+    Ok(())
+}
+
+/// Settles `user_account` and pays out all of its lamport rewards, so its stake
+/// can change afterwards without losing anything accrued so far. Token rewards
+/// stay settled in `token_rewards` until claimed with `claim_token_rewards`.
+/// An expired lock boost ends here too, see `expire_stake_lock`.
+fn settle_and_pay_rewards<'info>(
+    fundraising_account: &mut Account<'info, FundraisingAccount>,
+    user_account: &mut UserAccount,
+    user: &AccountInfo<'info>,
+) -> Result<u64> {
+    user_account.settle(fundraising_account)?;
+    fundraising_account.end_expired_boost(user_account, Clock::get()?.unix_timestamp)?;
+
+    let user_rewards = user_account.unclaimed_rewards;
+    user_account.unclaimed_rewards = 0;
+    user_account.last_claimed_fees_slot = Clock::get()?.slot;
+
+    **fundraising_account
+        .to_account_info()
+        .try_borrow_mut_lamports()? -= user_rewards;
+    **user.try_borrow_mut_lamports()? += user_rewards;
+
+    if user_rewards > 0 {
+        emit!(RewardsClaimed {
+            user: user.key(),
+            amount: user_rewards,
+        });
+    }
+
+    Ok(user_rewards)
+}
+
+/// Stakes `amount` shares, first paying out any lamport rewards accrued on the
+/// existing stake and settling its token rewards.
+///
+/// The whole stake is then locked for `lock_duration` seconds and earns rewards
+/// at that lock tier's multiplier. A lock can be extended but never shortened.
+pub fn stake_and_claim(ctx: Context<Stake>, amount: u64, lock_duration: i64) -> Result<()> {
+    let reward_multiplier_bps = ctx
+        .accounts
+        .protocol_config
+        .reward_multiplier_bps(lock_duration)?;
+    let lock_until = Clock::get()?.unix_timestamp + lock_duration;
+    require!(
+        lock_until >= ctx.accounts.user_stake.lock_until,
+        FundraisingErrors::LockShortened
+    );
 
     let fundraising_account = &mut ctx.accounts.fundraising_account;
-    let slot = Clock::get()?.slot;
 
     // Invoke the transfer instruction on the token program
     transfer(
@@ -303,75 +1149,958 @@ pub fn stake_and_claim(ctx: Context<Stake>, amount: u64) -> Result<()> {
         amount, // Transfer amount, adjust for decimals
     )?;
 
-    // Check if user already exists in the list of users staking
-    let sender_key = *ctx.accounts.sender.key;
-    let total_fees_collected = fundraising_account.total_fees_collected;
-    if let Some(user_account) = fundraising_account
-        .user_staking_info
-        .iter_mut()
-        .find(|user| user.key == sender_key)
-    {
-        // Update existing user account
-        user_account.amount_staked += amount;
-        user_account.total_fees_when_last_claimed = total_fees_collected;
-        user_account.last_claimed_fees_slot = slot;
-    } else {
-        // Add new user account
-        fundraising_account.user_staking_info.push(UserAccount {
-            key: sender_key,
-            amount_staked: amount,
-            total_fees_when_last_claimed: total_fees_collected,
-            last_claimed_fees_slot: slot,
-        });
-    }
+    // A fresh user stake account is all zeroes, so it has nothing to pay out
+    let user_account = &mut ctx.accounts.user_stake;
+    user_account.key = *ctx.accounts.sender.key;
+    settle_and_pay_rewards(
+        fundraising_account,
+        user_account,
+        &ctx.accounts.sender.to_account_info(),
+    )?;
+
+    let amount_staked = user_account.amount_staked + amount;
+    require!(amount_staked >= MIN_STAKE, FundraisingErrors::StakeTooSmall);
+    fundraising_account.set_user_stake(user_account, amount_staked, reward_multiplier_bps)?;
+    user_account.lock_until = lock_until;
 
-    fundraising_account.total_staked += amount;
+    emit!(Staked {
+        user: user_account.key,
+        amount,
+        amount_staked,
+        weighted_stake: user_account.weighted_stake,
+        lock_until,
+    });
 
     msg!("User staked {} tokens", amount);
 
     Ok(())
 }
 
-pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+/// Stops `amount` of the user's stake from earning rewards and queues it for
+/// withdrawal once the protocol's unstake cooldown has passed. Lamport rewards
+/// accrued so far are paid out first.
+pub fn request_unstake(ctx: Context<RequestUnstake>, amount: u64) -> Result<()> {
     let fundraising_account = &mut ctx.accounts.fundraising_account;
-    // let slot = Clock::get()?.slot;
+    let user_account = &mut ctx.accounts.user_stake;
+    require!(
+        amount > 0 && amount <= user_account.amount_staked,
+        FundraisingErrors::InsufficientStake
+    );
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now >= user_account.lock_until,
+        FundraisingErrors::StakeLocked
+    );
+
+    settle_and_pay_rewards(
+        fundraising_account,
+        user_account,
+        &ctx.accounts.user.to_account_info(),
+    )?;
+
+    let amount_staked = user_account.amount_staked - amount;
+    require!(
+        amount_staked == 0 || amount_staked >= MIN_STAKE,
+        FundraisingErrors::StakeTooSmall
+    );
+    let reward_multiplier_bps = user_account.reward_multiplier_bps;
+    fundraising_account.set_user_stake(user_account, amount_staked, reward_multiplier_bps)?;
+
+    let unlock_time = now + ctx.accounts.protocol_config.unstake_cooldown;
+    let unstake_request = &mut ctx.accounts.unstake_request;
+    unstake_request.user = ctx.accounts.user.key();
+    unstake_request.amount += amount;
+    unstake_request.unlock_time = unlock_time;
+
+    emit!(UnstakeRequested {
+        user: unstake_request.user,
+        amount,
+        pending_amount: unstake_request.amount,
+        unlock_time,
+    });
+
+    Ok(())
+}
+
+/// Sends the user's queued shares back once their cooldown has passed.
+pub fn withdraw_unstaked(ctx: Context<WithdrawUnstaked>) -> Result<()> {
+    let unstake_request = &ctx.accounts.unstake_request;
+    require!(
+        Clock::get()?.unix_timestamp >= unstake_request.unlock_time,
+        FundraisingErrors::UnstakeCooldownActive
+    );
 
     let signer_seeds: &[&[&[u8]]] = &[&[b"fundraising", &[ctx.bumps.fundraising_account]]];
 
-    // Invoke the transfer instruction on the token program
     transfer(
         CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
             Transfer {
                 from: ctx.accounts.sender_token_account.to_account_info(),
                 to: ctx.accounts.recipient_token_account.to_account_info(),
-                authority: fundraising_account.to_account_info(),
+                authority: ctx.accounts.fundraising_account.to_account_info(),
             },
         )
         .with_signer(signer_seeds),
-        amount, // Transfer amount, adjust for decimals
+        unstake_request.amount,
     )?;
 
-    // Check if user already exists in the list of users staking
-    let sender_key = *ctx.accounts.recipient.key;
-    if let Some(user_account) = fundraising_account
-        .user_staking_info
-        .iter_mut()
-        .find(|user| user.key == sender_key)
-    {
-        // Update existing user account
-        user_account.amount_staked -= amount;
-        // TODO
+    emit!(UnstakeWithdrawn {
+        user: unstake_request.user,
+        amount: unstake_request.amount,
+    });
+
+    Ok(())
+}
+
+/// Converts a fundraising account still in the legacy layout: every entry of
+/// its stake vector moves into a new `user_stake` PDA, passed in vector order as
+/// remaining accounts, and the account is rewritten in the current layout.
+pub fn migrate_fundraising_account<'info>(
+    ctx: Context<'_, '_, 'info, 'info, MigrateFundraisingAccount<'info>>,
+) -> Result<()> {
+    let fundraising_info = ctx.accounts.fundraising_account.to_account_info();
+    let legacy = LegacyFundraisingAccount::try_from_bytes(&fundraising_info.try_borrow_data()?)?;
+    let (fundraising_account, user_accounts) = legacy.migrate()?;
+    require!(
+        ctx.remaining_accounts.len() == user_accounts.len(),
+        FundraisingErrors::LegacyStakeAccountMismatch
+    );
+
+    for (user_account, user_stake) in user_accounts.iter().zip(ctx.remaining_accounts) {
+        let (address, bump) =
+            Pubkey::find_program_address(&[b"user_stake", user_account.key.as_ref()], &crate::ID);
+        require_keys_eq!(
+            user_stake.key(),
+            address,
+            FundraisingErrors::LegacyStakeAccountMismatch
+        );
+
+        create_user_stake(
+            &ctx.accounts.admin.to_account_info(),
+            user_stake,
+            &ctx.accounts.system_program.to_account_info(),
+            &[b"user_stake", user_account.key.as_ref(), &[bump]],
+        )?;
+        user_account.try_serialize(&mut &mut user_stake.try_borrow_mut_data()?[..])?;
     }
 
-    fundraising_account.total_staked -= amount;
+    // The legacy account is larger, its spare rent stays with the account
+    fundraising_info.realloc(ANCHOR_DISCRIMINATOR + FundraisingAccount::SIZE, false)?;
+    fundraising_account.try_serialize(&mut &mut fundraising_info.try_borrow_mut_data()?[..])?;
+
+    emit!(FundraisingAccountMigrated {
+        migrated_stakes: user_accounts.len() as u64,
+        total_staked: fundraising_account.total_staked,
+        legacy_start_time: legacy.start_time,
+        legacy_funds_raised: legacy.total_funds_raised,
+    });
+
+    Ok(())
+}
+
+/// Allocates a `user_stake` PDA for the program, like Anchor's `init`. An
+/// address someone already sent lamports to is topped up instead of failing.
+fn create_user_stake<'info>(
+    payer: &AccountInfo<'info>,
+    user_stake: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let space = ANCHOR_DISCRIMINATOR + UserAccount::SIZE;
+    let rent = Rent::get()?.minimum_balance(space);
+    let signer_seeds = &[seeds];
+
+    if user_stake.lamports() == 0 {
+        return system_program::create_account(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::CreateAccount {
+                    from: payer.clone(),
+                    to: user_stake.clone(),
+                },
+            )
+            .with_signer(signer_seeds),
+            rent,
+            space as u64,
+            &crate::ID,
+        );
+    }
+
+    let top_up = rent.saturating_sub(user_stake.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: user_stake.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new(
+            system_program.clone(),
+            system_program::Allocate {
+                account_to_allocate: user_stake.clone(),
+            },
+        )
+        .with_signer(signer_seeds),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new(
+            system_program.clone(),
+            system_program::Assign {
+                account_to_assign: user_stake.clone(),
+            },
+        )
+        .with_signer(signer_seeds),
+        &crate::ID,
+    )
+}
+
+/// Pays out the user's settled and newly accrued lamport rewards.
+pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+    let user_rewards = settle_and_pay_rewards(
+        &mut ctx.accounts.fundraising_account,
+        &mut ctx.accounts.user_stake,
+        &ctx.accounts.user.to_account_info(),
+    )?;
+
+    msg!("User claimed {} lamports", user_rewards);
+
+    Ok(())
+}
+
+/// Ends the boost of a stake whose lock has expired. Its owner may not settle
+/// for a long time, so anyone can call this to stop the stake from earning at
+/// the boosted rate. Rewards accrued so far are settled, not paid out.
+pub fn expire_stake_lock(ctx: Context<ExpireStakeLock>) -> Result<()> {
+    let fundraising_account = &mut ctx.accounts.fundraising_account;
+    let user_account = &mut ctx.accounts.user_stake;
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now >= user_account.lock_until,
+        FundraisingErrors::StakeLocked
+    );
+
+    user_account.settle(fundraising_account)?;
+    require!(
+        fundraising_account.end_expired_boost(user_account, now)?,
+        FundraisingErrors::StakeNotBoosted
+    );
+
+    Ok(())
+}
+
+/// Returns a contribution to a round that ended below its soft cap, burning the
+/// shares minted for it.
+pub fn refund(ctx: Context<Refund>) -> Result<()> {
+    let fundraising_round = &mut ctx.accounts.fundraising_round;
+    let contribution = &ctx.accounts.contribution;
+
+    let now = Clock::get()?.unix_timestamp;
+    require!(
+        now > fundraising_round.end_time,
+        FundraisingErrors::RoundNotEnded
+    );
+    require!(
+        fundraising_round.failed(now),
+        FundraisingErrors::SoftCapReached
+    );
+
+    let round_key = fundraising_round.key();
+    let user_key = ctx.accounts.user.key();
+    let signer_seeds: &[&[&[u8]]] = &[&[
+        b"contribution",
+        round_key.as_ref(),
+        user_key.as_ref(),
+        &[ctx.bumps.contribution],
+    ]];
+
+    // Nothing is released from a failed round, so every share is still in the vault
+    burn(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.token_mint.to_account_info(),
+                from: ctx.accounts.vesting_vault.to_account_info(),
+                authority: contribution.to_account_info(),
+            },
+        )
+        .with_signer(signer_seeds),
+        contribution.tokens,
+    )?;
+
+    close_account(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.vesting_vault.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: contribution.to_account_info(),
+            },
+        )
+        .with_signer(signer_seeds),
+    )?;
+
+    **fundraising_round
+        .to_account_info()
+        .try_borrow_mut_lamports()? -= contribution.amount;
+    **ctx.accounts.user.try_borrow_mut_lamports()? += contribution.amount;
+    fundraising_round.total_funds_raised -= contribution.amount;
 
-    msg!("User unstaked {} tokens", amount);
+    emit!(ContributionRefunded {
+        round: fundraising_round.key(),
+        user: contribution.user,
+        amount: contribution.amount,
+        tokens: contribution.tokens,
+    });
 
     Ok(())
 }
 
+/// Pays the user's settled and newly accrued rewards in `fee_mint`.
+pub fn claim_token_rewards(ctx: Context<ClaimTokenRewards>) -> Result<()> {
+    let fundraising_account = &mut ctx.accounts.fundraising_account;
+    let mint = ctx.accounts.fee_mint.key();
+    let user_key = ctx.accounts.user.key();
+
+    let user_account = &mut ctx.accounts.user_stake;
+    user_account.settle(fundraising_account)?;
+    fundraising_account.end_expired_boost(user_account, Clock::get()?.unix_timestamp)?;
+    let user_rewards = fundraising_account
+        .token_fees_for(&mint)
+        .map_or(0, |token_fees| {
+            user_account.take_token_rewards(token_fees.pool_id)
+        });
+
+    let signer_seeds: &[&[&[u8]]] = &[&[b"fundraising", &[ctx.bumps.fundraising_account]]];
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.fee_vault.to_account_info(),
+                to: ctx.accounts.user_fee_token_account.to_account_info(),
+                authority: fundraising_account.to_account_info(),
+            },
+        )
+        .with_signer(signer_seeds),
+        user_rewards,
+    )?;
+
+    emit!(TokenRewardsClaimed {
+        user: user_key,
+        mint,
+        amount: user_rewards,
+    });
+
+    Ok(())
+}
+
+#[event]
+pub struct FundraisingRoundStarted {
+    pub round: Pubkey,
+    pub index: u64,
+    pub kind: RoundKind,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub hard_cap: u64,
+    pub soft_cap: u64,
+    pub price_per_token: u64,
+    pub cliff_duration: i64,
+    pub vesting_duration: i64,
+}
+
+#[event]
+pub struct SharesReleased {
+    pub round: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub released: u64,
+    pub tokens: u64,
+}
+
+#[event]
+pub struct ContributionRefunded {
+    pub round: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub tokens: u64,
+}
+
+#[event]
+pub struct Staked {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub amount_staked: u64,
+    pub weighted_stake: u64,
+    pub lock_until: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct UnstakeRequested {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub pending_amount: u64,
+    pub unlock_time: i64,
+}
+
+#[event]
+pub struct UnstakeWithdrawn {
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct FundraisingAccountMigrated {
+    pub migrated_stakes: u64,
+    pub total_staked: u64,
+    pub legacy_start_time: i64,
+    // Raised by the legacy sale, these lamports stay in the fundraising account
+    pub legacy_funds_raised: u64,
+}
+
+#[event]
+pub struct LockBoostEnded {
+    pub user: Pubkey,
+    pub lock_until: i64,
+    pub weighted_stake: u64,
+}
+
+#[event]
+pub struct TokenRewardsClaimed {
+    pub user: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
 #[error_code]
 pub enum FundraisingErrors {
     FundraisingPeriodEnded,
+    InvalidFundraisingParameters,
+    HardCapReached,
+    PreviousRoundActive,
+    RoundNotEnded,
+    SoftCapReached,
+    InvalidVestingSchedule,
+    RoundFailed,
+    NothingVested,
+    TooManyFeeMints,
+    InsufficientStake,
+    UnstakeCooldownActive,
+    StakeLocked,
+    LockShortened,
+    StakeNotBoosted,
+    StakeOverflow,
+    ContributionTooSmall,
+    ShareOverflow,
+    StakeTooSmall,
+    RewardOverflow,
+    NotLegacyFundraisingAccount,
+    LegacyStakeAccountMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_fees(count: usize) -> Vec<TokenFees> {
+        (0..count)
+            .map(|pool_id| TokenFees {
+                mint: Pubkey::new_unique(),
+                pool_id: pool_id as u64,
+                amount: u64::MAX,
+                reward_per_token: u128::MAX,
+                undistributed_fees: u64::MAX,
+            })
+            .collect()
+    }
+
+    fn fundraising_account() -> FundraisingAccount {
+        FundraisingAccount {
+            token_mint: Pubkey::new_unique(),
+            round_count: 0,
+            last_round_end_time: 0,
+            total_fees_collected: 0,
+            token_fees_collected: vec![],
+            next_token_pool_id: 0,
+            total_staked: 0,
+            reward_per_token: 0,
+            undistributed_fees: 0,
+        }
+    }
+
+    fn user_account() -> UserAccount {
+        UserAccount {
+            key: Pubkey::new_unique(),
+            amount_staked: 0,
+            reward_multiplier_bps: 0,
+            weighted_stake: 0,
+            lock_until: 0,
+            reward_debt: 0,
+            unclaimed_rewards: 0,
+            last_claimed_fees_slot: 0,
+            token_rewards: vec![],
+        }
+    }
+
+    /// Settles `user_account` and adds `amount` to its 1x stake, like `stake_and_claim`.
+    fn stake(
+        fundraising_account: &mut FundraisingAccount,
+        user_account: &mut UserAccount,
+        amount: u64,
+    ) {
+        user_account.settle(fundraising_account).unwrap();
+        let amount_staked = user_account.amount_staked + amount;
+        fundraising_account
+            .set_user_stake(user_account, amount_staked, BASIS_POINTS as u16)
+            .unwrap();
+    }
+
+    fn claim_token_rewards(
+        fundraising_account: &FundraisingAccount,
+        user_account: &mut UserAccount,
+        mint: &Pubkey,
+    ) -> u64 {
+        user_account.settle(fundraising_account).unwrap();
+        let pool_id = fundraising_account.token_fees_for(mint).unwrap().pool_id;
+        user_account.take_token_rewards(pool_id)
+    }
+
+    #[test]
+    fn distribute_fees_holds_fees_back_until_something_is_staked() {
+        let mut fundraising_account = fundraising_account();
+        let mut staker = user_account();
+
+        fundraising_account.distribute_fees(500).unwrap();
+        assert_eq!(fundraising_account.reward_per_token, 0);
+        assert_eq!(fundraising_account.undistributed_fees, 500);
+
+        stake(&mut fundraising_account, &mut staker, 1_000);
+        fundraising_account.distribute_fees(100).unwrap();
+        assert_eq!(fundraising_account.undistributed_fees, 0);
+        assert_eq!(fundraising_account.total_fees_collected, 600);
+        assert_eq!(
+            fundraising_account.reward_per_token,
+            600 * REWARD_PRECISION / 1_000
+        );
+
+        staker.settle(&fundraising_account).unwrap();
+        assert_eq!(staker.unclaimed_rewards, 600);
+    }
+
+    #[test]
+    fn settle_pays_late_stakers_only_fees_collected_after_they_staked() {
+        let mut fundraising_account = fundraising_account();
+        let (mut early, mut late) = (user_account(), user_account());
+
+        stake(&mut fundraising_account, &mut early, 100);
+        fundraising_account.distribute_fees(1_000).unwrap();
+        // Joining right before a claim earns nothing from earlier fees
+        stake(&mut fundraising_account, &mut late, 900);
+        late.settle(&fundraising_account).unwrap();
+        assert_eq!(late.unclaimed_rewards, 0);
+
+        fundraising_account.distribute_fees(1_000).unwrap();
+        early.settle(&fundraising_account).unwrap();
+        late.settle(&fundraising_account).unwrap();
+        assert_eq!(early.unclaimed_rewards, 1_000 + 100);
+        assert_eq!(late.unclaimed_rewards, 900);
+    }
+
+    #[test]
+    fn settle_only_credits_new_rewards() {
+        let mut fundraising_account = fundraising_account();
+        let mut staker = user_account();
+
+        stake(&mut fundraising_account, &mut staker, 100);
+        fundraising_account.distribute_fees(1_000).unwrap();
+        staker.settle(&fundraising_account).unwrap();
+        staker.settle(&fundraising_account).unwrap();
+        assert_eq!(staker.unclaimed_rewards, 1_000);
+
+        // A top-up keeps what was settled and earns on the new stake from here on
+        stake(&mut fundraising_account, &mut staker, 100);
+        fundraising_account.distribute_fees(1_000).unwrap();
+        staker.settle(&fundraising_account).unwrap();
+        assert_eq!(staker.unclaimed_rewards, 2_000);
+    }
+
+    #[test]
+    fn rewards_round_down_and_never_exceed_fees() {
+        let mut fundraising_account = fundraising_account();
+        let mut stakers = [user_account(), user_account(), user_account()];
+
+        for (staker, amount) in stakers.iter_mut().zip([100, 200, 300]) {
+            stake(&mut fundraising_account, staker, amount * 1_000_000_000);
+        }
+        for _ in 0..10 {
+            fundraising_account.distribute_fees(10_000_000).unwrap();
+        }
+
+        let rewards: Vec<u64> = stakers
+            .iter_mut()
+            .map(|staker| {
+                staker.settle(&fundraising_account).unwrap();
+                staker.unclaimed_rewards
+            })
+            .collect();
+        // Each fee adds 16_666_666_666_666.67 to reward_per_token, rounded down
+        assert_eq!(rewards, [16_666_666, 33_333_333, 49_999_999]);
+        assert!(rewards.iter().sum::<u64>() <= fundraising_account.total_fees_collected);
+    }
+
+    #[test]
+    fn set_user_stake_keeps_total_staked_weighted() {
+        let mut fundraising_account = fundraising_account();
+        let (mut locked, mut unlocked) = (user_account(), user_account());
+
+        stake(&mut fundraising_account, &mut unlocked, 100);
+        locked.settle(&fundraising_account).unwrap();
+        fundraising_account
+            .set_user_stake(&mut locked, 60, 15_000)
+            .unwrap();
+        assert_eq!(locked.weighted_stake, 90);
+        assert_eq!(fundraising_account.total_staked, 190);
+
+        fundraising_account.distribute_fees(190).unwrap();
+        locked.settle(&fundraising_account).unwrap();
+        assert_eq!(locked.unclaimed_rewards, 90);
+
+        fundraising_account
+            .set_user_stake(&mut locked, 60, BASIS_POINTS as u16)
+            .unwrap();
+        assert_eq!(fundraising_account.total_staked, 160);
+    }
+
+    #[test]
+    fn rewards_stay_exact_after_one_base_unit_earns_a_fee_alone() {
+        let mut fundraising_account = fundraising_account();
+        let mut tiny = user_account();
+        fundraising_account
+            .set_user_stake(&mut tiny, 1, BASIS_POINTS as u16)
+            .unwrap();
+        // 1 SOL for a single base unit
+        fundraising_account.distribute_fees(1_000_000_000).unwrap();
+        assert_eq!(
+            fundraising_account.reward_per_token,
+            1_000_000_000 * REWARD_PRECISION
+        );
+
+        // weighted_stake * reward_per_token is 10^39 here, past u128::MAX
+        let mut large = user_account();
+        fundraising_account
+            .set_user_stake(&mut large, 1_000_000_000_000, BASIS_POINTS as u16)
+            .unwrap();
+        assert_eq!(large.reward_debt, 1_000_000_000_000_000_000_000);
+
+        fundraising_account.distribute_fees(1_000_000_000).unwrap();
+        tiny.settle(&fundraising_account).unwrap();
+        large.settle(&fundraising_account).unwrap();
+        assert_eq!(tiny.unclaimed_rewards, 1_000_000_000);
+        assert_eq!(large.unclaimed_rewards, 999_999_999);
+    }
+
+    #[test]
+    fn reward_math_reports_overflow_instead_of_panicking() {
+        assert!(accrued(u64::MAX, u128::MAX).is_err());
+        assert_eq!(
+            accrued(u64::MAX, REWARD_PRECISION).unwrap(),
+            u64::MAX as u128
+        );
+
+        let mut reward_per_token = u128::MAX;
+        let mut undistributed_fees = 0;
+        assert!(distribute(&mut reward_per_token, &mut undistributed_fees, 1, 1).is_err());
+
+        let mut undistributed_fees = u64::MAX;
+        assert!(distribute(&mut 0, &mut undistributed_fees, 1, 0).is_err());
+    }
+
+    #[test]
+    fn set_user_stake_rejects_weighted_stake_overflow() {
+        let mut fundraising_account = fundraising_account();
+        let mut staker = user_account();
+
+        assert!(fundraising_account
+            .set_user_stake(&mut staker, u64::MAX, 15_000)
+            .is_err());
+        assert_eq!(staker.weighted_stake, 0);
+
+        // Each stake fits on its own, but not their sum
+        let mut other = user_account();
+        fundraising_account
+            .set_user_stake(&mut staker, u64::MAX, BASIS_POINTS as u16)
+            .unwrap();
+        assert!(fundraising_account
+            .set_user_stake(&mut other, 1, BASIS_POINTS as u16)
+            .is_err());
+    }
+
+    #[test]
+    fn end_expired_boost_waits_for_the_lock_to_expire() {
+        let mut fundraising_account = fundraising_account();
+        let mut locked = user_account();
+        locked.lock_until = 100;
+        fundraising_account
+            .set_user_stake(&mut locked, 100, 15_000)
+            .unwrap();
+        fundraising_account.distribute_fees(1_500).unwrap();
+
+        locked.settle(&fundraising_account).unwrap();
+        assert!(!fundraising_account
+            .end_expired_boost(&mut locked, 99)
+            .unwrap());
+        assert!(fundraising_account
+            .end_expired_boost(&mut locked, 100)
+            .unwrap());
+        assert!(!fundraising_account
+            .end_expired_boost(&mut locked, 101)
+            .unwrap());
+
+        assert_eq!(locked.weighted_stake, 100);
+        assert_eq!(fundraising_account.total_staked, 100);
+        // Settled at the boosted rate, earning at 1x from here on
+        assert_eq!(locked.unclaimed_rewards, 1_500);
+        fundraising_account.distribute_fees(100).unwrap();
+        locked.settle(&fundraising_account).unwrap();
+        assert_eq!(locked.unclaimed_rewards, 1_600);
+    }
+
+    #[test]
+    fn end_expired_boost_ignores_fresh_and_unboosted_stakes() {
+        let mut fundraising_account = fundraising_account();
+        let mut fresh = user_account();
+        assert!(!fundraising_account
+            .end_expired_boost(&mut fresh, 1_000)
+            .unwrap());
+        assert_eq!(fresh.reward_multiplier_bps, 0);
+
+        fundraising_account
+            .set_user_stake(&mut fresh, 100, BASIS_POINTS as u16)
+            .unwrap();
+        assert!(!fundraising_account
+            .end_expired_boost(&mut fresh, 1_000)
+            .unwrap());
+        assert_eq!(fresh.weighted_stake, 100);
+    }
+
+    #[test]
+    fn token_rewards_follow_the_stake_at_the_time_of_each_fee() {
+        let mut fundraising_account = fundraising_account();
+        let (mut early, mut late) = (user_account(), user_account());
+        let mint = Pubkey::new_unique();
+
+        stake(&mut fundraising_account, &mut early, 100);
+        fundraising_account.record_token_fee(mint, 1_000).unwrap();
+        stake(&mut fundraising_account, &mut late, 300);
+        fundraising_account.record_token_fee(mint, 400).unwrap();
+
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut late, &mint),
+            300
+        );
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut early, &mint),
+            1_100
+        );
+        // Nothing is paid twice
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut early, &mint),
+            0
+        );
+        assert_eq!(fundraising_account.token_fees(&mint), 1_400);
+    }
+
+    #[test]
+    fn token_rewards_survive_a_stake_top_up() {
+        let mut fundraising_account = fundraising_account();
+        let (mut staker, mut other) = (user_account(), user_account());
+        let mint = Pubkey::new_unique();
+
+        stake(&mut fundraising_account, &mut staker, 100);
+        stake(&mut fundraising_account, &mut other, 100);
+        fundraising_account.record_token_fee(mint, 1_000).unwrap();
+        stake(&mut fundraising_account, &mut staker, 200);
+        fundraising_account.record_token_fee(mint, 400).unwrap();
+
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut staker, &mint),
+            500 + 300
+        );
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut other, &mint),
+            500 + 100
+        );
+    }
+
+    #[test]
+    fn token_fees_wait_for_the_first_staker() {
+        let mut fundraising_account = fundraising_account();
+        let mut staker = user_account();
+        let mint = Pubkey::new_unique();
+
+        fundraising_account.record_token_fee(mint, 500).unwrap();
+        stake(&mut fundraising_account, &mut staker, 100);
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut staker, &mint),
+            0
+        );
+
+        fundraising_account.record_token_fee(mint, 100).unwrap();
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut staker, &mint),
+            600
+        );
+    }
+
+    #[test]
+    fn token_pools_are_kept_once_every_slot_is_taken() {
+        let mut fundraising_account = fundraising_account();
+        let mut staker = user_account();
+        let mints: Vec<Pubkey> = (0..=MAX_FEE_MINTS).map(|_| Pubkey::new_unique()).collect();
+
+        stake(&mut fundraising_account, &mut staker, 100);
+        for mint in &mints[..MAX_FEE_MINTS] {
+            fundraising_account.record_token_fee(*mint, 1_000).unwrap();
+        }
+        assert!(fundraising_account
+            .token_pool(mints[MAX_FEE_MINTS])
+            .is_err());
+        assert!(fundraising_account
+            .record_token_fee(mints[MAX_FEE_MINTS], 1_000)
+            .is_err());
+
+        // A mint taken off the whitelist and added back resumes its pool
+        assert_eq!(fundraising_account.token_pool(mints[1]).unwrap(), 1);
+        fundraising_account.record_token_fee(mints[1], 200).unwrap();
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut staker, &mints[1]),
+            1_200
+        );
+        assert_eq!(
+            claim_token_rewards(&fundraising_account, &mut staker, &mints[0]),
+            1_000
+        );
+        assert_eq!(staker.token_rewards.len(), MAX_FEE_MINTS);
+    }
+
+    #[test]
+    fn fundraising_account_size_fits_every_fee_mint() {
+        let fundraising_account = FundraisingAccount {
+            token_mint: Pubkey::new_unique(),
+            round_count: u64::MAX,
+            last_round_end_time: i64::MAX,
+            total_fees_collected: u64::MAX,
+            token_fees_collected: token_fees(MAX_FEE_MINTS),
+            next_token_pool_id: u64::MAX,
+            total_staked: u64::MAX,
+            reward_per_token: u128::MAX,
+            undistributed_fees: u64::MAX,
+        };
+
+        assert_eq!(
+            fundraising_account.try_to_vec().unwrap().len(),
+            FundraisingAccount::SIZE
+        );
+    }
+
+    #[test]
+    fn user_account_size_fits_every_fee_mint() {
+        let user_account = UserAccount {
+            key: Pubkey::new_unique(),
+            amount_staked: u64::MAX,
+            reward_multiplier_bps: u16::MAX,
+            weighted_stake: u64::MAX,
+            lock_until: i64::MAX,
+            reward_debt: u128::MAX,
+            unclaimed_rewards: u64::MAX,
+            last_claimed_fees_slot: u64::MAX,
+            token_rewards: (0..MAX_FEE_MINTS)
+                .map(|pool_id| TokenRewards {
+                    pool_id: pool_id as u64,
+                    reward_debt: u128::MAX,
+                    unclaimed_rewards: u64::MAX,
+                })
+                .collect(),
+        };
+
+        assert_eq!(user_account.try_to_vec().unwrap().len(), UserAccount::SIZE);
+    }
+
+    fn legacy_account_bytes(total_fees_collected: u64, entries: &[(Pubkey, u64, u64)]) -> Vec<u8> {
+        let mut data = FundraisingAccount::discriminator().to_vec();
+        data.extend_from_slice(&1_000i64.to_le_bytes());
+        data.extend_from_slice(&2_000i64.to_le_bytes());
+        data.extend_from_slice(&5_000u64.to_le_bytes());
+        data.extend_from_slice(Pubkey::default().as_ref());
+        data.extend_from_slice(&total_fees_collected.to_le_bytes());
+        data.extend_from_slice(&entries.iter().map(|e| e.1).sum::<u64>().to_le_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (key, amount_staked, fees_when_last_claimed) in entries {
+            data.extend_from_slice(key.as_ref());
+            data.extend_from_slice(&amount_staked.to_le_bytes());
+            data.extend_from_slice(&fees_when_last_claimed.to_le_bytes());
+            data.extend_from_slice(&7u64.to_le_bytes());
+        }
+        data.resize(LegacyFundraisingAccount::SPACE, 0);
+        data
+    }
+
+    #[test]
+    fn legacy_stakes_migrate_with_their_pending_rewards() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let data = legacy_account_bytes(1_000, &[(alice, 3 * MIN_STAKE, 0), (bob, MIN_STAKE, 600)]);
+
+        let legacy = LegacyFundraisingAccount::try_from_bytes(&data).unwrap();
+        assert_eq!(legacy.total_funds_raised, 5_000);
+        assert_eq!(legacy.user_staking_info.len(), 2);
+
+        let (fundraising, users) = legacy.migrate().unwrap();
+        assert_eq!(fundraising.total_staked, 4 * MIN_STAKE);
+        assert_eq!(fundraising.total_fees_collected, 1_000);
+        assert_eq!(fundraising.last_round_end_time, 2_000);
+        assert_eq!(users[0].key, alice);
+        assert_eq!(users[0].weighted_stake, 3 * MIN_STAKE);
+        assert_eq!(users[0].unclaimed_rewards, 750);
+        assert_eq!(users[1].key, bob);
+        assert_eq!(users[1].unclaimed_rewards, 100);
+        assert_eq!(users[1].last_claimed_fees_slot, 7);
+
+        // Migrated stakes earn new fees like any other 1x stake
+        let mut fundraising = fundraising;
+        let mut alice_stake = users[0].clone();
+        alice_stake.settle(&fundraising).unwrap();
+        fundraising.distribute_fees(400).unwrap();
+        alice_stake.settle(&fundraising).unwrap();
+        assert_eq!(alice_stake.unclaimed_rewards, 1_050);
+    }
+
+    #[test]
+    fn only_the_legacy_layout_is_migrated() {
+        let data = legacy_account_bytes(0, &[]);
+        let current_size = data[..ANCHOR_DISCRIMINATOR + FundraisingAccount::SIZE].to_vec();
+        let mut other_account = data.clone();
+        other_account[0] ^= 1;
+
+        assert_eq!(
+            LegacyFundraisingAccount::try_from_bytes(&current_size).unwrap_err(),
+            FundraisingErrors::NotLegacyFundraisingAccount.into()
+        );
+        assert_eq!(
+            LegacyFundraisingAccount::try_from_bytes(&other_account).unwrap_err(),
+            FundraisingErrors::NotLegacyFundraisingAccount.into()
+        );
+    }
 }
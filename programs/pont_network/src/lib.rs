@@ -1,23 +1,40 @@
+mod config;
+mod escrow;
+mod fees;
 mod fundraising;
+mod merkle;
+mod treasury;
 
-use anchor_spl::token::{Mint, TokenAccount};
+use anchor_spl::token::{Token, TokenAccount};
 use itertools::izip;
 use std::vec;
 
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::native_token::LAMPORTS_PER_SOL;
+use config::*;
+use escrow::*;
+use fees::*;
 use fundraising::*;
+use merkle::*;
+use treasury::*;
 
 const ANCHOR_DISCRIMINATOR: usize = 8;
 const PUBKEY_SIZE: usize = 32;
-const FINGERPRINT_SIZE: usize = 32;
-const TX_COST: u64 = (LAMPORTS_PER_SOL as f64 * 0.01) as u64;
+const ARCHIVED_SAILING_SIZE: usize = PUBKEY_SIZE + 32 + 8 + 8 + 8;
+const OBSERVER_ACCESS_SIZE: usize = 1 + (1 + 8) + (1 + 8);
+const EMPTY_MASTER_KEY: [u8; 128] = [0u8; 128];
+const MAX_ORGANISATION_LEN: usize = 64;
+const MAX_PURPOSE_LEN: usize = 256;
+const OBSERVER_REQUEST_EXPIRY: i64 = 30 * 24 * 60 * 60; // 30 days
 
 declare_id!("8h6Ei5DT8ygysAaygguxZFKWcgnPhd9qLFHbvjREYFcR");
 
 #[program]
 pub mod pont_network {
-    use anchor_lang::solana_program::{self, blake3::hash, system_instruction};
+    use anchor_lang::solana_program::{
+        self,
+        blake3::{hash, hashv},
+        system_instruction,
+    };
 
     use super::*;
 
@@ -25,7 +42,9 @@ pub mod pont_network {
         let ship_account = &mut ctx.accounts.ship_account;
         ship_account.ship = ship;
         ship_account.data_accounts = Vec::new();
+        ship_account.archived_sailings = Vec::new();
         ship_account.ship_management = *ctx.accounts.ship_management.key;
+        ship_account.fingerprint_credits = 0;
 
         msg!("Ship account initialized");
 
@@ -50,6 +69,13 @@ pub mod pont_network {
             external_observers_x25519_pks.len()
         );
 
+        collect_fee(
+            &ctx.accounts.ship.to_account_info(),
+            &mut ctx.accounts.fundraising_account,
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.protocol_config.fee_schedule.data_account_fee,
+        )?;
+
         let ship_account = &mut ctx.accounts.ship_account;
         ship_account
             .data_accounts
@@ -60,68 +86,143 @@ pub mod pont_network {
 
         let data_account = &mut ctx.accounts.data_account;
         data_account.ship = *ctx.accounts.ship.key;
-        data_account.fingerprints = Vec::new();
+        data_account.fingerprint_tree = FingerprintTree::new();
+        data_account.is_finalized = false;
+        data_account.end_timestamp = 0;
+        data_account.final_digest = [0u8; 32];
+        data_account.key_epoch = 0;
 
         let external_observers_account = &mut ctx.accounts.external_observers_account;
         external_observers_account.unapproved_external_observers = Vec::new();
         external_observers_account.unapproved_external_observers_x25519_pks = Vec::new();
+        external_observers_account.unapproved_external_observers_applications = Vec::new();
         external_observers_account.external_observers_master_keys = external_observers_keys.clone();
         external_observers_account.external_observers = external_observers.clone();
         external_observers_account.external_observers_x25519_pks =
             external_observers_x25519_pks.clone();
+        external_observers_account.external_observers_access =
+            vec![ObserverAccess::default(); external_observers.len()];
 
         emit!(DataAccountInitialized {
             ship: *ctx.accounts.ship.key,
             data_account: data_account.key(),
-            external_observers: external_observers,
-            external_observers_keys: external_observers_keys,
+            external_observers,
+            external_observers_keys,
         });
 
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn external_observer_request(
         ctx: Context<ExternalObserverRequest>,
         external_observer_x25519_pk: Pubkey,
+        organisation: String,
+        purpose: String,
+        requested_from: Option<i64>,
+        requested_until: Option<i64>,
+        deposit: u64,
     ) -> Result<()> {
         let external_observers_account = &mut ctx.accounts.external_observers_account;
         let external_observer = *ctx.accounts.external_observer.key;
 
-        if !external_observers_account
+        if external_observers_account
             .unapproved_external_observers
             .contains(&external_observer)
         {
-            external_observers_account
-                .unapproved_external_observers
-                .push(external_observer);
-
-            external_observers_account
-                .unapproved_external_observers_x25519_pks
-                .push(external_observer_x25519_pk);
+            return Err(CustomErrors::ExternalObserverAlreadyRequested.into());
+        }
 
-            emit!(ExternalObserverRequested {
-                data_account: ctx.accounts.data_account.key(),
-                external_observer: external_observer,
-            });
+        let application = ExternalObserverApplication {
+            organisation,
+            purpose,
+            requested_from,
+            requested_until,
+            deposit,
+            requested_at: Clock::get()?.unix_timestamp,
+        };
+        application.validate()?;
+
+        // An empty deposit account would only cost rent and block a later deposit
+        require!(
+            deposit > 0 || ctx.accounts.deposit_account.is_none(),
+            CustomErrors::UnexpectedDepositAccount
+        );
 
-            Ok(())
-        } else {
-            Err(CustomErrors::ExternalObserverAlreadyRequested.into())
+        if deposit > 0 {
+            let deposit_account = ctx
+                .accounts
+                .deposit_account
+                .as_mut()
+                .ok_or(CustomErrors::DepositAccountMissing)?;
+            deposit_account.amount = deposit;
+
+            let transfer_instruction = system_instruction::transfer(
+                &external_observer,
+                &deposit_account.key(),
+                deposit,
+            );
+
+            solana_program::program::invoke(
+                &transfer_instruction,
+                &[
+                    ctx.accounts.external_observer.to_account_info(),
+                    deposit_account.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
         }
+
+        external_observers_account
+            .unapproved_external_observers
+            .push(external_observer);
+
+        external_observers_account
+            .unapproved_external_observers_x25519_pks
+            .push(external_observer_x25519_pk);
+
+        external_observers_account
+            .unapproved_external_observers_applications
+            .push(application.clone());
+
+        emit!(ExternalObserverRequested {
+            data_account: ctx.accounts.data_account.key(),
+            external_observer,
+            application,
+        });
+
+        Ok(())
     }
 
     pub fn add_external_observer(
         ctx: Context<AddExternalObserver>,
         external_observer_to_be_approved: Pubkey,
         external_observer_encrypted_master_key: [u8; 128],
+        role: ObserverRole,
+        valid_from: Option<i64>,
+        valid_until: Option<i64>,
     ) -> Result<()> {
+        let access = ObserverAccess {
+            role,
+            valid_from,
+            valid_until,
+        };
+        access.validate(&external_observer_encrypted_master_key)?;
+
+        collect_fee(
+            &ctx.accounts.ship_management.to_account_info(),
+            &mut ctx.accounts.fundraising_account,
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.protocol_config.fee_schedule.observer_approval_fee,
+        )?;
+
         let external_observers_account = &mut ctx.accounts.external_observers_account;
 
         let eo_index = external_observers_account
             .unapproved_external_observers
             .iter()
             .position(|&x| x == external_observer_to_be_approved)
-            .unwrap();
+            .ok_or(CustomErrors::ExternalObserverRequestNotFound)?;
 
         external_observers_account
             .unapproved_external_observers
@@ -129,6 +230,16 @@ pub mod pont_network {
         let eo_x25519_pk = external_observers_account
             .unapproved_external_observers_x25519_pks
             .remove(eo_index);
+        let application = external_observers_account
+            .unapproved_external_observers_applications
+            .remove(eo_index);
+        // The deposit account is closed to the requester, refunding the deposit
+        if application.deposit > 0 {
+            require!(
+                ctx.accounts.deposit_account.is_some(),
+                CustomErrors::DepositAccountMissing
+            );
+        }
 
         external_observers_account
             .external_observers
@@ -142,6 +253,10 @@ pub mod pont_network {
             .external_observers_master_keys
             .push(external_observer_encrypted_master_key);
 
+        external_observers_account
+            .external_observers_access
+            .push(access);
+
         emit!(ExternalObserverAdded {
             data_account: ctx.accounts.data_account.key(),
             external_observer: external_observer_to_be_approved,
@@ -149,6 +264,242 @@ pub mod pont_network {
             ship_account: ctx.accounts.ship_account.key(),
             ship_management: ctx.accounts.ship_management.key(),
             external_observer_encrypted_master_key,
+            role,
+            valid_from,
+            valid_until,
+        });
+
+        Ok(())
+    }
+
+    pub fn reject_external_observer_request(
+        ctx: Context<RejectExternalObserverRequest>,
+        external_observer_to_be_rejected: Pubkey,
+    ) -> Result<()> {
+        let external_observers_account = &mut ctx.accounts.external_observers_account;
+
+        let eo_index = external_observers_account
+            .unapproved_external_observers
+            .iter()
+            .position(|&x| x == external_observer_to_be_rejected)
+            .ok_or(CustomErrors::ExternalObserverRequestNotFound)?;
+
+        external_observers_account
+            .unapproved_external_observers
+            .remove(eo_index);
+        external_observers_account
+            .unapproved_external_observers_x25519_pks
+            .remove(eo_index);
+        let application = external_observers_account
+            .unapproved_external_observers_applications
+            .remove(eo_index);
+        // The deposit account is closed to the requester, refunding the deposit
+        if application.deposit > 0 {
+            require!(
+                ctx.accounts.deposit_account.is_some(),
+                CustomErrors::DepositAccountMissing
+            );
+        }
+
+        emit!(ExternalObserverRejected {
+            data_account: ctx.accounts.data_account.key(),
+            external_observer: external_observer_to_be_rejected,
+            external_observers_account: ctx.accounts.external_observers_account.key(),
+            ship_account: ctx.accounts.ship_account.key(),
+            ship_management: ctx.accounts.ship_management.key(),
+            deposit_refunded: application.deposit,
+        });
+
+        Ok(())
+    }
+
+    pub fn expire_external_observer_request(
+        ctx: Context<ExpireExternalObserverRequest>,
+        external_observer_to_be_expired: Pubkey,
+    ) -> Result<()> {
+        let external_observers_account = &mut ctx.accounts.external_observers_account;
+
+        let eo_index = external_observers_account
+            .unapproved_external_observers
+            .iter()
+            .position(|&x| x == external_observer_to_be_expired)
+            .ok_or(CustomErrors::ExternalObserverRequestNotFound)?;
+
+        let application = &external_observers_account.unapproved_external_observers_applications[eo_index];
+        require!(
+            application.is_expired(Clock::get()?.unix_timestamp),
+            CustomErrors::ExternalObserverRequestNotExpired
+        );
+
+        external_observers_account
+            .unapproved_external_observers
+            .remove(eo_index);
+        external_observers_account
+            .unapproved_external_observers_x25519_pks
+            .remove(eo_index);
+        let application = external_observers_account
+            .unapproved_external_observers_applications
+            .remove(eo_index);
+        // The deposit account is closed to ship management, forfeiting the deposit
+        if application.deposit > 0 {
+            require!(
+                ctx.accounts.deposit_account.is_some(),
+                CustomErrors::DepositAccountMissing
+            );
+        }
+
+        emit!(ExternalObserverRequestExpired {
+            data_account: ctx.accounts.data_account.key(),
+            external_observer: external_observer_to_be_expired,
+            external_observers_account: ctx.accounts.external_observers_account.key(),
+            ship_account: ctx.accounts.ship_account.key(),
+            ship_management: ctx.accounts.ship_management.key(),
+            deposit_forfeited: application.deposit,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_external_observer(
+        ctx: Context<RemoveExternalObserver>,
+        external_observer_to_be_removed: Pubkey,
+    ) -> Result<()> {
+        let external_observers_account = &mut ctx.accounts.external_observers_account;
+
+        let eo_index = external_observers_account
+            .external_observers
+            .iter()
+            .position(|&x| x == external_observer_to_be_removed)
+            .ok_or(CustomErrors::ExternalObserverNotFound)?;
+
+        external_observers_account
+            .external_observers
+            .remove(eo_index);
+        external_observers_account
+            .external_observers_x25519_pks
+            .remove(eo_index);
+        external_observers_account
+            .external_observers_master_keys
+            .remove(eo_index);
+        external_observers_account
+            .external_observers_access
+            .remove(eo_index);
+
+        emit!(ExternalObserverRemoved {
+            data_account: ctx.accounts.data_account.key(),
+            external_observer: external_observer_to_be_removed,
+            external_observers_account: ctx.accounts.external_observers_account.key(),
+            ship_account: ctx.accounts.ship_account.key(),
+            ship_management: ctx.accounts.ship_management.key(),
+        });
+
+        Ok(())
+    }
+
+    pub fn rotate_master_key(
+        ctx: Context<RotateMasterKey>,
+        key_epoch: u32,
+        external_observers_master_keys: Vec<[u8; 128]>,
+    ) -> Result<()> {
+        let data_account = &mut ctx.accounts.data_account;
+        let external_observers_account = &mut ctx.accounts.external_observers_account;
+
+        require_eq!(
+            key_epoch,
+            data_account.key_epoch + 1,
+            CustomErrors::InvalidKeyEpoch
+        );
+        require_eq!(
+            external_observers_master_keys.len(),
+            external_observers_account.external_observers.len(),
+            CustomErrors::MasterKeyCountMismatch
+        );
+        for (access, master_key) in external_observers_account
+            .external_observers_access
+            .iter()
+            .zip(external_observers_master_keys.iter())
+        {
+            access.validate(master_key)?;
+        }
+
+        data_account.key_epoch = key_epoch;
+        external_observers_account.external_observers_master_keys =
+            external_observers_master_keys.clone();
+
+        emit!(MasterKeyRotated {
+            data_account: data_account.key(),
+            external_observers_account: external_observers_account.key(),
+            key_epoch,
+            external_observers: external_observers_account.external_observers.clone(),
+            external_observers_master_keys,
+        });
+
+        Ok(())
+    }
+
+    pub fn check_observer_access(
+        ctx: Context<CheckObserverAccess>,
+        external_observer: Pubkey,
+        required_role: ObserverRole,
+    ) -> Result<()> {
+        ctx.accounts.external_observers_account.require_access(
+            &external_observer,
+            required_role,
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    pub fn request_data_export(
+        ctx: Context<RequestDataExport>,
+        from_timestamp: u64,
+        to_timestamp: u64,
+    ) -> Result<()> {
+        require!(
+            from_timestamp <= to_timestamp,
+            CustomErrors::InvalidExportRange
+        );
+
+        let external_observer = ctx.accounts.external_observer.key();
+        ctx.accounts.external_observers_account.require_access(
+            &external_observer,
+            ObserverRole::Auditor,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        emit!(DataExportRequested {
+            data_account: ctx.accounts.data_account.key(),
+            external_observer,
+            from_timestamp,
+            to_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn purchase_credits(ctx: Context<PurchaseCredits>, credits: u64) -> Result<()> {
+        let credit_price = ctx.accounts.protocol_config.fee_schedule.credit_price;
+        require!(credit_price > 0, CustomErrors::CreditsNotForSale);
+
+        let price = credits
+            .checked_mul(credit_price)
+            .ok_or(ConfigErrors::FeeOverflow)?;
+
+        collect_fee(
+            &ctx.accounts.payer.to_account_info(),
+            &mut ctx.accounts.fundraising_account,
+            &ctx.accounts.system_program.to_account_info(),
+            price,
+        )?;
+
+        let ship_account = &mut ctx.accounts.ship_account;
+        ship_account.fingerprint_credits += credits;
+
+        emit!(CreditsPurchased {
+            ship: ship_account.ship,
+            payer: ctx.accounts.payer.key(),
+            credits,
+            price,
+            fingerprint_credits: ship_account.fingerprint_credits,
         });
 
         Ok(())
@@ -161,41 +512,24 @@ pub mod pont_network {
         iv: Vec<u8>,
         ciphertext_timestamp: u64,
     ) -> Result<()> {
-        let lamports_required = TX_COST;
+        ctx.accounts.charge_fee(1, false)?;
 
         let ship = &ctx.accounts.ship;
-
-        // Create the system transfer instruction
-        let transfer_instruction = system_instruction::transfer(
-            ctx.accounts.ship.key,
-            &ctx.accounts.fundraising_account.key(),
-            lamports_required,
-        );
-
-        ctx.accounts.fundraising_account.total_fees_collected += lamports_required;
-
-        // Send the transfer instruction
-        solana_program::program::invoke(
-            &transfer_instruction,
-            &[
-                ctx.accounts.ship.to_account_info(),
-                ctx.accounts.fundraising_account.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
-
         let data_account = &mut ctx.accounts.data_account;
-        let fingerprint = Fingerprint::from(hash(&ciphertext).to_bytes());
-        data_account.fingerprints.push(fingerprint.clone());
+        let fingerprint_bytes = hash(&ciphertext).to_bytes();
+        let leaf_index = data_account.fingerprint_tree.append(fingerprint_bytes)?;
 
         emit!(DataFingerprintAdded {
             ship: *ship.key,
-            fingerprint,
+            fingerprint: Fingerprint::from(fingerprint_bytes),
             ciphertext,
             tag,
             iv,
             ciphertext_timestamp,
-            data_account: ctx.accounts.data_account.key(),
+            data_account: data_account.key(),
+            leaf_index,
+            root: data_account.fingerprint_tree.root(),
+            key_epoch: data_account.key_epoch,
         });
 
         Ok(())
@@ -208,87 +542,311 @@ pub mod pont_network {
         ivs: Vec<Vec<u8>>,
         ciphertext_timestamps: Vec<u64>,
     ) -> Result<()> {
-        assert_eq!(ciphertexts.len(), ciphertext_timestamps.len());
+        // The fee is charged per ciphertext, so every ciphertext must be stored
+        let fingerprint_count = ciphertexts.len();
+        require!(
+            tags.len() == fingerprint_count
+                && ivs.len() == fingerprint_count
+                && ciphertext_timestamps.len() == fingerprint_count,
+            CustomErrors::BatchLengthMismatch
+        );
+
+        // One transfer for the whole batch
+        let (fee_mint, fee) = ctx.accounts.charge_fee(fingerprint_count, true)?;
+
+        let fundraising_account = &ctx.accounts.fundraising_account;
+        emit!(BatchFeeCollected {
+            ship: *ctx.accounts.ship.key,
+            data_account: ctx.accounts.data_account.key(),
+            fingerprint_count: fingerprint_count as u64,
+            fee_mint,
+            fee,
+            total_fees_collected: match fee_mint {
+                Some(mint) => fundraising_account.token_fees(&mint),
+                None => fundraising_account.total_fees_collected,
+            },
+        });
 
         let data_account = &mut ctx.accounts.data_account;
 
         for (ciphertext_instance, tag_instance, iv_instance, timestamp) in
             izip!(ciphertexts, tags, ivs, ciphertext_timestamps)
         {
-            let fingerprint = Fingerprint::from(hash(&ciphertext_instance).to_bytes());
-
-            data_account.fingerprints.push(fingerprint.clone());
+            let fingerprint_bytes = hash(&ciphertext_instance).to_bytes();
+            let leaf_index = data_account.fingerprint_tree.append(fingerprint_bytes)?;
 
             emit!(DataFingerprintAdded {
                 ship: *ctx.accounts.ship.key,
-                fingerprint,
-                ciphertext: ciphertext_instance.clone(),
+                fingerprint: Fingerprint::from(fingerprint_bytes),
+                ciphertext: ciphertext_instance,
                 tag: tag_instance,
                 iv: iv_instance,
                 ciphertext_timestamp: timestamp,
                 data_account: data_account.key(),
+                leaf_index,
+                root: data_account.fingerprint_tree.root(),
+                key_epoch: data_account.key_epoch,
             });
         }
 
         Ok(())
     }
 
-    pub fn reallocate_data_account(ctx: Context<ReallocateDataAccount>) -> Result<()> {
+    pub fn verify_fingerprint(
+        ctx: Context<VerifyFingerprint>,
+        fingerprint: [u8; 32],
+        leaf_index: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let data_account = &ctx.accounts.data_account;
+
+        let root = data_account
+            .fingerprint_tree
+            .verify(fingerprint, leaf_index, &proof)
+            .ok_or(CustomErrors::InvalidFingerprintProof)?;
+
+        emit!(FingerprintVerified {
+            data_account: data_account.key(),
+            fingerprint: Fingerprint::from(fingerprint),
+            leaf_index,
+            root,
+        });
+
+        Ok(())
+    }
+
+    pub fn finalize_data_account(ctx: Context<FinalizeDataAccount>) -> Result<()> {
+        let ship_account = &ctx.accounts.ship_account;
         let data_account = &mut ctx.accounts.data_account;
-        let new_size = data_account.to_account_info().data_len() + 10240;
-        data_account.to_account_info().realloc(new_size, false)?;
+
+        // Sealed at the cluster time, in milliseconds like the sailing's start
+        let end_timestamp = Clock::get()?.unix_timestamp as u64 * 1000;
+
+        let data_account_index = ship_account
+            .data_accounts
+            .iter()
+            .position(|&x| x == data_account.key())
+            .ok_or(CustomErrors::DataAccountNotFound)?;
+        require!(
+            end_timestamp >= ship_account.data_account_starting_timestamps[data_account_index],
+            CustomErrors::InvalidEndTimestamp
+        );
+
+        // The root alone cannot tell trailing zero leaves from empty slots, so the
+        // digest also binds the number of fingerprints.
+        let fingerprint_count = data_account.fingerprint_tree.next_index;
+        let final_root = data_account.fingerprint_tree.root();
+        let final_digest = hashv(&[&final_root, &fingerprint_count.to_le_bytes()]).to_bytes();
+
+        data_account.is_finalized = true;
+        data_account.end_timestamp = end_timestamp;
+        data_account.final_digest = final_digest;
+
+        emit!(DataAccountFinalized {
+            ship: ship_account.ship,
+            data_account: data_account.key(),
+            finalized_by: ctx.accounts.authority.key(),
+            end_timestamp,
+            fingerprint_count,
+            final_root,
+            final_digest,
+        });
+
+        Ok(())
+    }
+
+    pub fn archive_data_account(ctx: Context<ArchiveDataAccount>) -> Result<()> {
+        let data_account = &ctx.accounts.data_account;
+        let ship_account = &mut ctx.accounts.ship_account;
+
+        let data_account_index = ship_account
+            .data_accounts
+            .iter()
+            .position(|&x| x == data_account.key())
+            .ok_or(CustomErrors::DataAccountNotFound)?;
+
+        // Data account PDAs are derived from data_accounts.len(), so the entry stays
+        // in place and only the compact record is added.
+        let archived_sailing = ArchivedSailing {
+            data_account: data_account.key(),
+            final_root: data_account.fingerprint_tree.root(),
+            fingerprint_count: data_account.fingerprint_tree.next_index,
+            start_timestamp: ship_account.data_account_starting_timestamps[data_account_index],
+            end_timestamp: data_account.end_timestamp,
+        };
+        ship_account.archived_sailings.push(archived_sailing.clone());
+
+        let reclaimed_lamports = data_account.to_account_info().lamports()
+            + ctx.accounts.external_observers_account.to_account_info().lamports();
+
+        emit!(SailingArchived {
+            ship: ship_account.ship,
+            ship_management: ship_account.ship_management,
+            archived_sailing,
+            reclaimed_lamports,
+        });
+
         Ok(())
     }
 
-    pub fn start_fundraising(ctx: Context<StartFundraising>) -> Result<()> {
-        fundraising::start_fundraising(ctx)
+    pub fn initialize_protocol_config(
+        ctx: Context<InitializeProtocolConfig>,
+        fee_schedule: FeeSchedule,
+    ) -> Result<()> {
+        config::initialize_protocol_config(ctx, fee_schedule)
+    }
+
+    pub fn update_fee_schedule(
+        ctx: Context<UpdateProtocolConfig>,
+        fee_schedule: FeeSchedule,
+    ) -> Result<()> {
+        config::update_fee_schedule(ctx, fee_schedule)
+    }
+
+    pub fn set_protocol_admin(ctx: Context<UpdateProtocolConfig>, new_admin: Pubkey) -> Result<()> {
+        config::set_protocol_admin(ctx, new_admin)
+    }
+
+    pub fn set_fee_mint(ctx: Context<SetFeeMint>, fingerprint_fee: u64, batch_fee: u64) -> Result<()> {
+        config::set_fee_mint(ctx, fingerprint_fee, batch_fee)
+    }
+
+    pub fn remove_fee_mint(ctx: Context<RemoveFeeMint>, mint: Pubkey) -> Result<()> {
+        config::remove_fee_mint(ctx, mint)
+    }
+
+    pub fn set_lock_tiers(
+        ctx: Context<UpdateProtocolConfig>,
+        lock_tiers: Vec<LockTier>,
+    ) -> Result<()> {
+        config::set_lock_tiers(ctx, lock_tiers)
+    }
+
+    pub fn set_unstake_cooldown(
+        ctx: Context<UpdateProtocolConfig>,
+        unstake_cooldown: i64,
+    ) -> Result<()> {
+        config::set_unstake_cooldown(ctx, unstake_cooldown)
+    }
+
+    pub fn initialize_fee_escrow(
+        ctx: Context<InitializeFeeEscrow>,
+        low_balance_threshold: u64,
+    ) -> Result<()> {
+        escrow::initialize_fee_escrow(ctx, low_balance_threshold)
+    }
+
+    pub fn set_fee_escrow_threshold(
+        ctx: Context<UpdateFeeEscrow>,
+        low_balance_threshold: u64,
+    ) -> Result<()> {
+        escrow::set_fee_escrow_threshold(ctx, low_balance_threshold)
+    }
+
+    pub fn top_up_fee_escrow(ctx: Context<UpdateFeeEscrow>, amount: u64) -> Result<()> {
+        escrow::top_up_fee_escrow(ctx, amount)
+    }
+
+    pub fn withdraw_fee_escrow(ctx: Context<UpdateFeeEscrow>, amount: u64) -> Result<()> {
+        escrow::withdraw_fee_escrow(ctx, amount)
+    }
+
+    pub fn initialize_fundraising(
+        ctx: Context<InitializeFundraising>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        fundraising::initialize_fundraising(ctx, name, symbol, uri)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn start_fundraising_round(
+        ctx: Context<StartFundraisingRound>,
+        kind: RoundKind,
+        duration: i64,
+        hard_cap: u64,
+        soft_cap: u64,
+        price_per_token: u64,
+        cliff_duration: i64,
+        vesting_duration: i64,
+    ) -> Result<()> {
+        fundraising::start_fundraising_round(
+            ctx,
+            kind,
+            duration,
+            hard_cap,
+            soft_cap,
+            price_per_token,
+            cliff_duration,
+            vesting_duration,
+        )
     }
 
     pub fn contribute(ctx: Context<Contribute>, amount: u64) -> Result<()> {
         fundraising::contribute(ctx, amount)
     }
 
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
-    let fundraising_account = &mut ctx.accounts.fundraising_account;
-    let user = &mut ctx.accounts.user;
-
-    let total_staked = fundraising_account.total_staked;
-    let total_fees_collected = fundraising_account.total_fees_collected;
-
-    let user_account = match fundraising_account
-        .user_staking_info
-        .iter_mut()
-        .find(|x| x.key == *user.key)
-    {
-        Some(account) => account,
-        None => return Ok(()),
-    };
+    pub fn release_vested(ctx: Context<ReleaseVested>) -> Result<()> {
+        fundraising::release_vested(ctx)
+    }
 
-    let user_share_percentage = user_account.amount_staked as f64 / total_staked as f64;
+    pub fn refund(ctx: Context<Refund>) -> Result<()> {
+        fundraising::refund(ctx)
+    }
 
-    // Calculate the user's share of the rewards
-    let fees_since_last_claim =
-        total_fees_collected - user_account.total_fees_when_last_claimed;
+    pub fn initialize_treasury(
+        ctx: Context<InitializeTreasury>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        treasury::initialize_treasury(ctx, signers, threshold)
+    }
 
-    let user_rewards = (fees_since_last_claim as f64 * user_share_percentage) as u64;
+    pub fn propose_withdrawal(
+        ctx: Context<ProposeWithdrawal>,
+        destination: Pubkey,
+        amount: u64,
+    ) -> Result<()> {
+        treasury::propose_withdrawal(ctx, destination, amount)
+    }
 
-    // Transfer the user's share of the rewards
-    **fundraising_account
-        .to_account_info()
-        .try_borrow_mut_lamports()? -= user_rewards as u64;
-    **user.try_borrow_mut_lamports()? += user_rewards as u64;
+    pub fn approve_withdrawal(ctx: Context<ApproveWithdrawal>) -> Result<()> {
+        treasury::approve_withdrawal(ctx)
+    }
 
-    msg!("User claimed {} lamports", user_rewards);
+    pub fn execute_withdrawal(ctx: Context<ExecuteWithdrawal>) -> Result<()> {
+        treasury::execute_withdrawal(ctx)
+    }
 
-    Ok(())
-}
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        fundraising::claim_rewards(ctx)
+    }
+
+    pub fn claim_token_rewards(ctx: Context<ClaimTokenRewards>) -> Result<()> {
+        fundraising::claim_token_rewards(ctx)
+    }
+
+    pub fn expire_stake_lock(ctx: Context<ExpireStakeLock>) -> Result<()> {
+        fundraising::expire_stake_lock(ctx)
+    }
+
+    pub fn stake(ctx: Context<Stake>, amount: u64, lock_duration: i64) -> Result<()> {
+        fundraising::stake_and_claim(ctx, amount, lock_duration)
+    }
+
+    pub fn request_unstake(ctx: Context<RequestUnstake>, amount: u64) -> Result<()> {
+        fundraising::request_unstake(ctx, amount)
+    }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
-        fundraising::stake_and_claim(ctx, amount)
+    pub fn withdraw_unstaked(ctx: Context<WithdrawUnstaked>) -> Result<()> {
+        fundraising::withdraw_unstaked(ctx)
     }
 
-    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
-        fundraising::unstake(ctx, amount)
+    pub fn migrate_fundraising_account<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateFundraisingAccount<'info>>,
+    ) -> Result<()> {
+        fundraising::migrate_fundraising_account(ctx)
     }
 }
 
@@ -301,13 +859,103 @@ impl From<[u8; 32]> for Fingerprint {
     }
 }
 
-#[event]
-pub struct ShipInitialized {
-    pub ship: Pubkey,
-    pub ship_management: Pubkey,
+/// Justification attached to an external observer request, kept until it is
+/// approved, rejected or expired.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ExternalObserverApplication {
+    pub organisation: String,
+    pub purpose: String,
+    pub requested_from: Option<i64>,
+    pub requested_until: Option<i64>,
+    pub deposit: u64,
+    pub requested_at: i64,
 }
 
-#[event]
+impl ExternalObserverApplication {
+    pub fn size_for(organisation: &str, purpose: &str) -> usize {
+        4 + organisation.len() + 4 + purpose.len() + (1 + 8) + (1 + 8) + 8 + 8
+    }
+
+    pub fn size(&self) -> usize {
+        Self::size_for(&self.organisation, &self.purpose)
+    }
+
+    /// Whether ship management may expire the request and keep its deposit.
+    pub fn is_expired(&self, now: i64) -> bool {
+        now >= self.requested_at + OBSERVER_REQUEST_EXPIRY
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.organisation.len() <= MAX_ORGANISATION_LEN
+                && self.purpose.len() <= MAX_PURPOSE_LEN,
+            CustomErrors::ApplicationTooLong
+        );
+        if let (Some(requested_from), Some(requested_until)) =
+            (self.requested_from, self.requested_until)
+        {
+            require!(
+                requested_from < requested_until,
+                CustomErrors::InvalidAccessWindow
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Roles are ordered: every role has the permissions of the ones before it.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObserverRole {
+    // Can check fingerprints, never receives the master key
+    Verifier,
+    // Receives the master key and can decrypt the data
+    #[default]
+    Reader,
+    // Can additionally request data exports
+    Auditor,
+}
+
+/// Permissions of an approved observer. Access window bounds are in unix seconds,
+/// `None` leaves that side open.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct ObserverAccess {
+    pub role: ObserverRole,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+impl ObserverAccess {
+    pub fn validate(&self, master_key: &[u8; 128]) -> Result<()> {
+        if let (Some(valid_from), Some(valid_until)) = (self.valid_from, self.valid_until) {
+            require!(valid_from < valid_until, CustomErrors::InvalidAccessWindow);
+        }
+        if self.role == ObserverRole::Verifier {
+            require!(
+                *master_key == EMPTY_MASTER_KEY,
+                CustomErrors::MasterKeyNotAllowedForRole
+            );
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, now: i64) -> Result<()> {
+        if let Some(valid_from) = self.valid_from {
+            require!(now >= valid_from, CustomErrors::ObserverAccessNotYetValid);
+        }
+        if let Some(valid_until) = self.valid_until {
+            require!(now < valid_until, CustomErrors::ObserverAccessExpired);
+        }
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ShipInitialized {
+    pub ship: Pubkey,
+    pub ship_management: Pubkey,
+}
+
+#[event]
 pub struct DataAccountInitialized {
     pub ship: Pubkey,
     pub data_account: Pubkey,
@@ -324,12 +972,70 @@ pub struct DataFingerprintAdded {
     pub iv: Vec<u8>,
     pub ciphertext_timestamp: u64,
     pub data_account: Pubkey,
+    pub leaf_index: u64,
+    pub root: [u8; 32],
+    pub key_epoch: u32,
+}
+
+#[event]
+pub struct BatchFeeCollected {
+    pub ship: Pubkey,
+    pub data_account: Pubkey,
+    pub fingerprint_count: u64,
+    // None when paid in lamports
+    pub fee_mint: Option<Pubkey>,
+    pub fee: u64,
+    pub total_fees_collected: u64,
+}
+
+#[event]
+pub struct CreditsPurchased {
+    pub ship: Pubkey,
+    pub payer: Pubkey,
+    pub credits: u64,
+    pub price: u64,
+    pub fingerprint_credits: u64,
+}
+
+#[event]
+pub struct CreditsSpent {
+    pub ship: Pubkey,
+    pub credits_used: u64,
+    pub fingerprint_credits: u64,
+}
+
+#[event]
+pub struct FingerprintVerified {
+    pub data_account: Pubkey,
+    pub fingerprint: Fingerprint,
+    pub leaf_index: u64,
+    pub root: [u8; 32],
+}
+
+#[event]
+pub struct DataAccountFinalized {
+    pub ship: Pubkey,
+    pub data_account: Pubkey,
+    pub finalized_by: Pubkey,
+    pub end_timestamp: u64,
+    pub fingerprint_count: u64,
+    pub final_root: [u8; 32],
+    pub final_digest: [u8; 32],
+}
+
+#[event]
+pub struct SailingArchived {
+    pub ship: Pubkey,
+    pub ship_management: Pubkey,
+    pub archived_sailing: ArchivedSailing,
+    pub reclaimed_lamports: u64,
 }
 
 #[event]
 pub struct ExternalObserverRequested {
     pub data_account: Pubkey,
     pub external_observer: Pubkey,
+    pub application: ExternalObserverApplication,
 }
 
 #[event]
@@ -340,6 +1046,55 @@ pub struct ExternalObserverAdded {
     pub ship_account: Pubkey,
     pub ship_management: Pubkey,
     pub external_observer_encrypted_master_key: [u8; 128],
+    pub role: ObserverRole,
+    pub valid_from: Option<i64>,
+    pub valid_until: Option<i64>,
+}
+
+#[event]
+pub struct DataExportRequested {
+    pub data_account: Pubkey,
+    pub external_observer: Pubkey,
+    pub from_timestamp: u64,
+    pub to_timestamp: u64,
+}
+
+#[event]
+pub struct ExternalObserverRejected {
+    pub data_account: Pubkey,
+    pub external_observer: Pubkey,
+    pub external_observers_account: Pubkey,
+    pub ship_account: Pubkey,
+    pub ship_management: Pubkey,
+    pub deposit_refunded: u64,
+}
+
+#[event]
+pub struct ExternalObserverRequestExpired {
+    pub data_account: Pubkey,
+    pub external_observer: Pubkey,
+    pub external_observers_account: Pubkey,
+    pub ship_account: Pubkey,
+    pub ship_management: Pubkey,
+    pub deposit_forfeited: u64,
+}
+
+#[event]
+pub struct ExternalObserverRemoved {
+    pub data_account: Pubkey,
+    pub external_observer: Pubkey,
+    pub external_observers_account: Pubkey,
+    pub ship_account: Pubkey,
+    pub ship_management: Pubkey,
+}
+
+#[event]
+pub struct MasterKeyRotated {
+    pub data_account: Pubkey,
+    pub external_observers_account: Pubkey,
+    pub key_epoch: u32,
+    pub external_observers: Vec<Pubkey>,
+    pub external_observers_master_keys: Vec<[u8; 128]>,
 }
 
 #[account]
@@ -348,6 +1103,18 @@ pub struct ShipAccount {
     pub ship_management: Pubkey,
     pub data_accounts: Vec<Pubkey>,
     pub data_account_starting_timestamps: Vec<u64>,
+    pub archived_sailings: Vec<ArchivedSailing>,
+    // Prepaid fingerprints, used before any per-call fee
+    pub fingerprint_credits: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ArchivedSailing {
+    pub data_account: Pubkey,
+    pub final_root: [u8; 32],
+    pub fingerprint_count: u64,
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
 }
 
 impl ShipAccount {
@@ -358,7 +1125,10 @@ impl ShipAccount {
             + 4
             + (self.data_accounts.len() * 32)
             + 4
-            + (self.data_account_starting_timestamps.len() * 8);
+            + (self.data_account_starting_timestamps.len() * 8)
+            + 4
+            + (self.archived_sailings.len() * ARCHIVED_SAILING_SIZE)
+            + 8;
         msg!("Current ShipAccount size: {}", size);
         size
     }
@@ -367,16 +1137,28 @@ impl ShipAccount {
 #[account]
 pub struct DataAccount {
     pub ship: Pubkey,
-    pub fingerprints: Vec<Fingerprint>,
+    pub fingerprint_tree: FingerprintTree,
+    pub is_finalized: bool,
+    pub end_timestamp: u64,
+    pub final_digest: [u8; 32],
+    // Epoch of the master key that encrypts newly added fingerprints
+    pub key_epoch: u32,
+}
+
+#[account]
+pub struct ObserverRequestDeposit {
+    pub amount: u64,
 }
 
 #[account]
 pub struct ExternalObserversAccount {
     pub unapproved_external_observers: Vec<Pubkey>,
     pub unapproved_external_observers_x25519_pks: Vec<Pubkey>,
+    pub unapproved_external_observers_applications: Vec<ExternalObserverApplication>,
     pub external_observers: Vec<Pubkey>,
     pub external_observers_x25519_pks: Vec<Pubkey>,
     pub external_observers_master_keys: Vec<[u8; 128]>,
+    pub external_observers_access: Vec<ObserverAccess>,
 }
 
 impl ExternalObserversAccount {
@@ -387,14 +1169,54 @@ impl ExternalObserversAccount {
             + 4
             + (self.unapproved_external_observers_x25519_pks.len() * PUBKEY_SIZE)
             + 4
+            + self
+                .unapproved_external_observers_applications
+                .iter()
+                .map(|application| application.size())
+                .sum::<usize>()
+            + 4
             + (self.external_observers_x25519_pks.len() * PUBKEY_SIZE)
             + 4
             + (self.external_observers.len() * PUBKEY_SIZE)
             + 4
-            + (self.external_observers_master_keys.len() * 128);
+            + (self.external_observers_master_keys.len() * 128)
+            + 4
+            + (self.external_observers_access.len() * OBSERVER_ACCESS_SIZE);
         msg!("Current ExternalObserversAccount size: {}", size);
         size
     }
+
+    /// Bytes taken by the pending request of `external_observer`, excluding its keys.
+    pub fn application_size(&self, external_observer: &Pubkey) -> usize {
+        self.unapproved_external_observers
+            .iter()
+            .position(|x| x == external_observer)
+            .map_or(0, |eo_index| {
+                self.unapproved_external_observers_applications[eo_index].size()
+            })
+    }
+
+    /// Fails unless `external_observer` is approved with at least `required_role`
+    /// and `now` falls within its access window.
+    pub fn require_access(
+        &self,
+        external_observer: &Pubkey,
+        required_role: ObserverRole,
+        now: i64,
+    ) -> Result<()> {
+        let eo_index = self
+            .external_observers
+            .iter()
+            .position(|x| x == external_observer)
+            .ok_or(CustomErrors::ExternalObserverNotFound)?;
+
+        let access = &self.external_observers_access[eo_index];
+        require!(
+            access.role >= required_role,
+            CustomErrors::InsufficientObserverRole
+        );
+        access.check(now)
+    }
 }
 
 #[derive(Accounts)]
@@ -403,7 +1225,7 @@ pub struct InitializeShip<'info> {
     #[account(
         init,
         payer = ship_management,
-        space = 8 + PUBKEY_SIZE + PUBKEY_SIZE + 4 + 4,
+        space = 8 + PUBKEY_SIZE + PUBKEY_SIZE + 4 + 4 + 4 + 8,
         seeds = [b"ship_account", ship.key().as_ref()],
         bump
     )]
@@ -431,16 +1253,16 @@ pub struct AddDataAccount<'info> {
     #[account(
         init,
         payer = ship,
-        space = ANCHOR_DISCRIMINATOR + PUBKEY_SIZE + 4 + FINGERPRINT_SIZE * 240, // 10080 minutes per week, 1440 minutes per day, 240 minutes per 4 hours
+        space = ANCHOR_DISCRIMINATOR + PUBKEY_SIZE + FingerprintTree::SIZE + 1 + 8 + 32 + 4,
         seeds = [b"data_account", ship.key().as_ref(), ship_account.data_accounts.len().to_le_bytes().as_ref()],
         bump
     )]
-    pub data_account: Account<'info, DataAccount>,
+    pub data_account: Box<Account<'info, DataAccount>>,
     #[account(
         init,
         payer = ship,
         space = {
-            let new_size = ANCHOR_DISCRIMINATOR + 4 + 4 + 4 + external_observers_x25519_pks.len() * PUBKEY_SIZE + 4 + external_observers.len() * PUBKEY_SIZE + 4 + external_observers_keys.len() * 128;
+            let new_size = ANCHOR_DISCRIMINATOR + 4 + 4 + 4 + 4 + external_observers_x25519_pks.len() * PUBKEY_SIZE + 4 + external_observers.len() * PUBKEY_SIZE + 4 + external_observers_keys.len() * 128 + 4 + external_observers.len() * OBSERVER_ACCESS_SIZE;
             msg!("New ExternalObserversAccount size: {}", new_size);
             new_size
         },
@@ -450,6 +1272,10 @@ pub struct AddDataAccount<'info> {
     pub external_observers_account: Account<'info, ExternalObserversAccount>,
     #[account(mut)]
     pub ship: Signer<'info>,
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     pub system_program: Program<'info, System>,
 }
 
@@ -457,72 +1283,540 @@ pub struct AddDataAccount<'info> {
 pub struct AddDataFingerprint<'info> {
     #[account(mut)]
     pub ship: Signer<'info>,
-    #[account(mut, has_one = ship)]
-    pub data_account: Account<'info, DataAccount>,
-    /// CHECK: check account is this program
+    #[account(
+        mut,
+        has_one = ship,
+        constraint = !data_account.is_finalized @ CustomErrors::DataAccountFinalized
+    )]
+    pub data_account: Box<Account<'info, DataAccount>>,
     #[account(mut, seeds = [b"fundraising"], bump)]
     pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+
+    // Only passed when paying the fee in a whitelisted SPL mint
+    #[account(mut)]
+    pub fee_payer_token_account: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub fee_vault: Option<Account<'info, TokenAccount>>,
+    pub token_program: Option<Program<'info, Token>>,
+
+    // Only passed when ship management sponsors the lamport fee
+    #[account(mut, seeds = [b"fee_escrow", fee_escrow.ship_management.as_ref()], bump)]
+    pub fee_escrow: Option<Account<'info, FeeEscrow>>,
+    // Always required so prepaid credits are never skipped
+    #[account(mut, seeds = [b"ship_account", ship.key().as_ref()], bump)]
+    pub ship_account: Account<'info, ShipAccount>,
+}
+
+impl<'info> AddDataFingerprint<'info> {
+    /// Charges the fee for `fingerprint_count` fingerprints. Prepaid credits on
+    /// `ship_account` cover fingerprint fees first, while a batch always pays its
+    /// batch fee. The rest is paid in the mint of `fee_payer_token_account` when
+    /// passed and in lamports otherwise. Lamport fees come from the fleet's
+    /// `fee_escrow` when passed, else from the ship.
+    /// Returns the mint paid in and the amount.
+    fn charge_fee(
+        &mut self,
+        fingerprint_count: usize,
+        batched: bool,
+    ) -> Result<(Option<Pubkey>, u64)> {
+        let fingerprint_count = fingerprint_count - self.spend_credits(fingerprint_count);
+        if fingerprint_count == 0 && !batched {
+            return Ok((None, 0));
+        }
+
+        let fee_schedule = &self.protocol_config.fee_schedule;
+
+        let Some(payer_token_account) = &self.fee_payer_token_account else {
+            let batch_fee = if batched { fee_schedule.batch_fee } else { 0 };
+            let fee =
+                fee_schedule.fee_for(fee_schedule.fingerprint_fee, batch_fee, fingerprint_count)?;
+
+            if let Some(fee_escrow) = &self.fee_escrow {
+                require_keys_eq!(
+                    self.ship_account.ship_management,
+                    fee_escrow.ship_management,
+                    EscrowErrors::EscrowNotForShip
+                );
+
+                collect_fee_from_escrow(fee_escrow, &mut self.fundraising_account, fee)?;
+                return Ok((None, fee));
+            }
+
+            collect_fee(
+                &self.ship.to_account_info(),
+                &mut self.fundraising_account,
+                &self.system_program.to_account_info(),
+                fee,
+            )?;
+
+            return Ok((None, fee));
+        };
+
+        let (Some(fee_vault), Some(token_program)) = (&self.fee_vault, &self.token_program) else {
+            return err!(FeeErrors::FeeTokenAccountsMissing);
+        };
+
+        let fee_mint = self.protocol_config.fee_mint(&payer_token_account.mint)?;
+        let batch_fee = if batched { fee_mint.batch_fee } else { 0 };
+        let fee = fee_schedule.fee_for(fee_mint.fingerprint_fee, batch_fee, fingerprint_count)?;
+
+        collect_token_fee(
+            &self.ship,
+            payer_token_account,
+            fee_vault,
+            &mut self.fundraising_account,
+            token_program,
+            fee,
+        )?;
+
+        Ok((Some(fee_mint.mint), fee))
+    }
+
+    /// Spends up to `fingerprint_count` prepaid credits, returning how many were used.
+    fn spend_credits(&mut self, fingerprint_count: usize) -> usize {
+        let ship_account = &mut self.ship_account;
+        let credits_used = ship_account.fingerprint_credits.min(fingerprint_count as u64);
+        if credits_used == 0 {
+            return 0;
+        }
+        ship_account.fingerprint_credits -= credits_used;
+
+        emit!(CreditsSpent {
+            ship: ship_account.ship,
+            credits_used,
+            fingerprint_credits: ship_account.fingerprint_credits,
+        });
+
+        credits_used as usize
+    }
+}
+
+#[derive(Accounts)]
+pub struct PurchaseCredits<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, seeds = [b"ship_account", ship_account.ship.as_ref()], bump)]
+    pub ship_account: Account<'info, ShipAccount>,
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyFingerprint<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeDataAccount<'info> {
+    #[account(
+        mut,
+        constraint = !data_account.is_finalized @ CustomErrors::DataAccountFinalized
+    )]
+    pub data_account: Box<Account<'info, DataAccount>>,
+    #[account(
+        seeds = [b"ship_account", data_account.ship.as_ref()],
+        bump,
+        constraint = authority.key() == ship_account.ship
+            || authority.key() == ship_account.ship_management @ CustomErrors::UnauthorizedSailingAuthority
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ArchiveDataAccount<'info> {
+    #[account(
+        mut,
+        has_one = ship_management,
+        realloc = ship_account.get_size() + ARCHIVED_SAILING_SIZE,
+        realloc::payer = ship_management,
+        realloc::zero = false,
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
+    #[account(
+        mut,
+        close = ship_management,
+        constraint = data_account.ship == ship_account.ship,
+        constraint = data_account.is_finalized @ CustomErrors::DataAccountNotFinalized
+    )]
+    pub data_account: Box<Account<'info, DataAccount>>,
+    // Pending requests must be rejected or expired first, their deposit
+    // accounts can't be closed once this account is gone
+    #[account(
+        mut,
+        close = ship_management,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump,
+        constraint = external_observers_account.unapproved_external_observers.is_empty()
+            @ CustomErrors::PendingObserverRequests
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    #[account(mut)]
+    pub ship_management: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(external_observer_x25519_pk: Pubkey)]
+#[instruction(external_observer_x25519_pk: Pubkey, organisation: String, purpose: String)]
 pub struct ExternalObserverRequest<'info> {
     #[account(
         mut,
         seeds = [b"external_observers_account", data_account.key().as_ref()],
         bump,
-        realloc = external_observers_account.get_size() + PUBKEY_SIZE + PUBKEY_SIZE,
+        realloc = external_observers_account.get_size()
+            + PUBKEY_SIZE
+            + PUBKEY_SIZE
+            + ExternalObserverApplication::size_for(&organisation, &purpose),
         realloc::payer = external_observer,
         realloc::zero = false
     )]
     pub external_observers_account: Account<'info, ExternalObserversAccount>,
-    pub data_account: Account<'info, DataAccount>,
+    pub data_account: Box<Account<'info, DataAccount>>,
+    // Only needed when a deposit is attached to the request
+    #[account(
+        init,
+        payer = external_observer,
+        space = ANCHOR_DISCRIMINATOR + 8,
+        seeds = [b"observer_request_deposit", external_observers_account.key().as_ref(), external_observer.key().as_ref()],
+        bump
+    )]
+    pub deposit_account: Option<Account<'info, ObserverRequestDeposit>>,
     #[account(mut)]
     pub external_observer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
+#[instruction(external_observer_to_be_approved: Pubkey)]
 pub struct AddExternalObserver<'info> {
-    pub data_account: Account<'info, DataAccount>,
+    pub data_account: Box<Account<'info, DataAccount>>,
     #[account(
         mut,
-        realloc = external_observers_account.get_size() + 128,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump,
+        realloc = (external_observers_account.get_size() + 128 + OBSERVER_ACCESS_SIZE)
+            .saturating_sub(external_observers_account.application_size(&external_observer_to_be_approved)),
         realloc::payer = ship_management,
         realloc::zero = false
     )]
     pub external_observers_account: Account<'info, ExternalObserversAccount>,
-    #[account(has_one = ship_management)]
+    #[account(
+        has_one = ship_management,
+        constraint = ship_account.ship == data_account.ship
+    )]
     pub ship_account: Account<'info, ShipAccount>,
     #[account(mut)]
     pub ship_management: Signer<'info>,
+    #[account(mut, address = external_observer_to_be_approved)]
+    pub external_observer: SystemAccount<'info>,
+    #[account(
+        mut,
+        close = external_observer,
+        seeds = [b"observer_request_deposit", external_observers_account.key().as_ref(), external_observer.key().as_ref()],
+        bump
+    )]
+    pub deposit_account: Option<Account<'info, ObserverRequestDeposit>>,
+    #[account(mut, seeds = [b"fundraising"], bump)]
+    pub fundraising_account: Account<'info, FundraisingAccount>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ReallocateDataAccount<'info> {
-    #[account(mut, has_one = ship)]
-    pub data_account: Account<'info, DataAccount>,
-    #[account(mut)]
-    pub ship: Signer<'info>,
+#[instruction(external_observer_to_be_rejected: Pubkey)]
+pub struct RejectExternalObserverRequest<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+    // Shrinking returns the realloc rent paid in external_observer_request to the requester
+    #[account(
+        mut,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump,
+        realloc = external_observers_account.get_size().saturating_sub(
+            PUBKEY_SIZE
+                + PUBKEY_SIZE
+                + external_observers_account.application_size(&external_observer_to_be_rejected)
+        ),
+        realloc::payer = external_observer,
+        realloc::zero = false
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    #[account(
+        has_one = ship_management,
+        constraint = ship_account.ship == data_account.ship
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
+    pub ship_management: Signer<'info>,
+    #[account(mut, address = external_observer_to_be_rejected)]
+    pub external_observer: SystemAccount<'info>,
+    #[account(
+        mut,
+        close = external_observer,
+        seeds = [b"observer_request_deposit", external_observers_account.key().as_ref(), external_observer.key().as_ref()],
+        bump
+    )]
+    pub deposit_account: Option<Account<'info, ObserverRequestDeposit>>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ClaimRewards<'info> {
+#[instruction(external_observer_to_be_expired: Pubkey)]
+pub struct ExpireExternalObserverRequest<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+    // The realloc rent still goes back to the requester, only the deposit is forfeited
+    #[account(
+        mut,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump,
+        realloc = external_observers_account.get_size().saturating_sub(
+            PUBKEY_SIZE
+                + PUBKEY_SIZE
+                + external_observers_account.application_size(&external_observer_to_be_expired)
+        ),
+        realloc::payer = external_observer,
+        realloc::zero = false
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    #[account(
+        has_one = ship_management,
+        constraint = ship_account.ship == data_account.ship
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
     #[account(mut)]
-    pub user: Signer<'info>,
-    #[account(mut, seeds = [b"fundraising"], bump)]
-    pub fundraising_account: Account<'info, FundraisingAccount>,
-    #[account(mut, associated_token::mint = token_mint, associated_token::authority = user)]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub ship_management: Signer<'info>,
+    #[account(mut, address = external_observer_to_be_expired)]
+    pub external_observer: SystemAccount<'info>,
+    #[account(
+        mut,
+        close = ship_management,
+        seeds = [b"observer_request_deposit", external_observers_account.key().as_ref(), external_observer.key().as_ref()],
+        bump
+    )]
+    pub deposit_account: Option<Account<'info, ObserverRequestDeposit>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveExternalObserver<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+    #[account(
+        mut,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump,
+        realloc = external_observers_account.get_size().saturating_sub(PUBKEY_SIZE + PUBKEY_SIZE + 128 + OBSERVER_ACCESS_SIZE),
+        realloc::payer = ship_management,
+        realloc::zero = false
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    #[account(
+        has_one = ship_management,
+        constraint = ship_account.ship == data_account.ship
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
     #[account(mut)]
-    pub token_mint: Account<'info, Mint>,
+    pub ship_management: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct CheckObserverAccess<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+    #[account(
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+}
+
+#[derive(Accounts)]
+pub struct RequestDataExport<'info> {
+    pub data_account: Box<Account<'info, DataAccount>>,
+    #[account(
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    pub external_observer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RotateMasterKey<'info> {
+    #[account(mut)]
+    pub data_account: Box<Account<'info, DataAccount>>,
+    #[account(
+        mut,
+        seeds = [b"external_observers_account", data_account.key().as_ref()],
+        bump
+    )]
+    pub external_observers_account: Account<'info, ExternalObserversAccount>,
+    #[account(
+        has_one = ship_management,
+        constraint = ship_account.ship == data_account.ship
+    )]
+    pub ship_account: Account<'info, ShipAccount>,
+    pub ship_management: Signer<'info>,
+}
+
 #[error_code]
 pub enum CustomErrors {
     ExternalObserverAlreadyRequested,
+    FingerprintTreeFull,
+    InvalidFingerprintProof,
+    DataAccountFinalized,
+    DataAccountNotFound,
+    InvalidEndTimestamp,
+    UnauthorizedSailingAuthority,
+    DataAccountNotFinalized,
+    ExternalObserverNotFound,
+    ExternalObserverRequestNotFound,
+    InvalidKeyEpoch,
+    MasterKeyCountMismatch,
+    InvalidAccessWindow,
+    ObserverAccessNotYetValid,
+    ObserverAccessExpired,
+    MasterKeyNotAllowedForRole,
+    InsufficientObserverRole,
+    InvalidExportRange,
+    ApplicationTooLong,
+    DepositAccountMissing,
+    ExternalObserverRequestNotExpired,
+    CreditsNotForSale,
+    UnexpectedDepositAccount,
+    PendingObserverRequests,
+    BatchLengthMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn application(requested_at: i64) -> ExternalObserverApplication {
+        ExternalObserverApplication {
+            organisation: "Lloyd's Register".to_string(),
+            purpose: "Hull insurance claim".to_string(),
+            requested_from: None,
+            requested_until: None,
+            deposit: 100_000_000,
+            requested_at,
+        }
+    }
+
+    #[test]
+    fn observer_request_expires_after_expiry_period() {
+        let application = application(1_000);
+
+        assert!(!application.is_expired(1_000));
+        assert!(!application.is_expired(1_000 + OBSERVER_REQUEST_EXPIRY - 1));
+        assert!(application.is_expired(1_000 + OBSERVER_REQUEST_EXPIRY));
+    }
+
+    fn access(
+        role: ObserverRole,
+        valid_from: Option<i64>,
+        valid_until: Option<i64>,
+    ) -> ObserverAccess {
+        ObserverAccess {
+            role,
+            valid_from,
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn observer_access_window_includes_start_and_excludes_end() {
+        let window = access(ObserverRole::Reader, Some(1_000), Some(2_000));
+
+        assert_eq!(
+            window.check(999),
+            Err(CustomErrors::ObserverAccessNotYetValid.into())
+        );
+        assert_eq!(window.check(1_000), Ok(()));
+        assert_eq!(window.check(1_999), Ok(()));
+        assert_eq!(
+            window.check(2_000),
+            Err(CustomErrors::ObserverAccessExpired.into())
+        );
+
+        let open = access(ObserverRole::Reader, None, None);
+        assert_eq!(open.check(i64::MIN), Ok(()));
+        assert_eq!(open.check(i64::MAX), Ok(()));
+    }
+
+    #[test]
+    fn observer_access_rejects_inverted_or_empty_windows() {
+        let master_key = [1; 128];
+
+        assert_eq!(
+            access(ObserverRole::Reader, Some(2_000), Some(1_000)).validate(&master_key),
+            Err(CustomErrors::InvalidAccessWindow.into())
+        );
+        assert_eq!(
+            access(ObserverRole::Reader, Some(1_000), Some(1_000)).validate(&master_key),
+            Err(CustomErrors::InvalidAccessWindow.into())
+        );
+        assert_eq!(
+            access(ObserverRole::Reader, Some(1_000), Some(1_001)).validate(&master_key),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn verifiers_never_receive_the_master_key() {
+        let verifier = access(ObserverRole::Verifier, None, None);
+
+        assert_eq!(
+            verifier.validate(&[1; 128]),
+            Err(CustomErrors::MasterKeyNotAllowedForRole.into())
+        );
+        assert_eq!(verifier.validate(&EMPTY_MASTER_KEY), Ok(()));
+        assert_eq!(
+            access(ObserverRole::Auditor, None, None).validate(&[1; 128]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn require_access_honours_role_ordering_and_window() {
+        let reader = Pubkey::new_unique();
+        let auditor = Pubkey::new_unique();
+        let observers = ExternalObserversAccount {
+            unapproved_external_observers: vec![],
+            unapproved_external_observers_x25519_pks: vec![],
+            unapproved_external_observers_applications: vec![],
+            external_observers: vec![reader, auditor],
+            external_observers_x25519_pks: vec![Pubkey::default(); 2],
+            external_observers_master_keys: vec![[1; 128]; 2],
+            external_observers_access: vec![
+                access(ObserverRole::Reader, None, None),
+                access(ObserverRole::Auditor, Some(1_000), Some(2_000)),
+            ],
+        };
+
+        assert!(ObserverRole::Verifier < ObserverRole::Reader);
+        assert!(ObserverRole::Reader < ObserverRole::Auditor);
+
+        assert_eq!(observers.require_access(&reader, ObserverRole::Verifier, 0), Ok(()));
+        assert_eq!(observers.require_access(&reader, ObserverRole::Reader, 0), Ok(()));
+        assert_eq!(
+            observers.require_access(&reader, ObserverRole::Auditor, 0),
+            Err(CustomErrors::InsufficientObserverRole.into())
+        );
+
+        assert_eq!(observers.require_access(&auditor, ObserverRole::Auditor, 1_000), Ok(()));
+        assert_eq!(
+            observers.require_access(&auditor, ObserverRole::Verifier, 2_000),
+            Err(CustomErrors::ObserverAccessExpired.into())
+        );
+
+        assert_eq!(
+            observers.require_access(&Pubkey::new_unique(), ObserverRole::Verifier, 0),
+            Err(CustomErrors::ExternalObserverNotFound.into())
+        );
+    }
 }
@@ -0,0 +1,261 @@
+use anchor_lang::{prelude::*, solana_program::blake3::hashv};
+
+use crate::CustomErrors;
+
+pub const MERKLE_TREE_DEPTH: usize = 20; // 1,048,576 fingerprints per sailing
+pub const MERKLE_ROOT_HISTORY_SIZE: usize = 32;
+pub const MERKLE_NODE_SIZE: usize = 32;
+
+/// Append-only Merkle tree over the fingerprints of a sailing.
+///
+/// Only the rightmost filled node of every level (the frontier) and a ring buffer
+/// of recent roots are stored, so the account never grows. Leaves themselves are
+/// recoverable from `DataFingerprintAdded` events.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FingerprintTree {
+    pub next_index: u64,
+    pub current_root_index: u64,
+    pub filled_subtrees: [[u8; 32]; MERKLE_TREE_DEPTH],
+    pub roots: [[u8; 32]; MERKLE_ROOT_HISTORY_SIZE],
+    // Leaves in the tree when the root at the same position in `roots` was current
+    pub root_leaf_counts: [u64; MERKLE_ROOT_HISTORY_SIZE],
+}
+
+impl FingerprintTree {
+    pub const SIZE: usize = 8
+        + 8
+        + MERKLE_TREE_DEPTH * MERKLE_NODE_SIZE
+        + MERKLE_ROOT_HISTORY_SIZE * MERKLE_NODE_SIZE
+        + MERKLE_ROOT_HISTORY_SIZE * 8;
+
+    pub fn new() -> Self {
+        let mut zero = [0u8; 32];
+        let mut filled_subtrees = [[0u8; 32]; MERKLE_TREE_DEPTH];
+        for subtree in filled_subtrees.iter_mut() {
+            *subtree = zero;
+            zero = hash_pair(&zero, &zero);
+        }
+
+        let mut roots = [[0u8; 32]; MERKLE_ROOT_HISTORY_SIZE];
+        roots[0] = zero;
+
+        FingerprintTree {
+            next_index: 0,
+            current_root_index: 0,
+            filled_subtrees,
+            roots,
+            root_leaf_counts: [0; MERKLE_ROOT_HISTORY_SIZE],
+        }
+    }
+
+    /// Appends a leaf and returns its index.
+    pub fn append(&mut self, leaf: [u8; 32]) -> Result<u64> {
+        require!(
+            self.next_index < 1u64 << MERKLE_TREE_DEPTH,
+            CustomErrors::FingerprintTreeFull
+        );
+
+        let leaf_index = self.next_index;
+        let mut index = leaf_index;
+        let mut current = leaf;
+        let mut zero = [0u8; 32];
+
+        for subtree in self.filled_subtrees.iter_mut() {
+            if index & 1 == 0 {
+                *subtree = current;
+                current = hash_pair(&current, &zero);
+            } else {
+                current = hash_pair(subtree, &current);
+            }
+            zero = hash_pair(&zero, &zero);
+            index >>= 1;
+        }
+
+        self.current_root_index = (self.current_root_index + 1) % MERKLE_ROOT_HISTORY_SIZE as u64;
+        self.next_index += 1;
+        self.roots[self.current_root_index as usize] = current;
+        self.root_leaf_counts[self.current_root_index as usize] = self.next_index;
+
+        Ok(leaf_index)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.roots[self.current_root_index as usize]
+    }
+
+    /// Looks `root` up in the root history, so proofs built shortly before
+    /// further appends still verify. Returns how many leaves the tree held then.
+    pub fn leaf_count_at(&self, root: &[u8; 32]) -> Option<u64> {
+        self.roots
+            .iter()
+            .zip(self.root_leaf_counts)
+            .find(|(known_root, leaf_count)| *known_root == root && *leaf_count > 0)
+            .map(|(_, leaf_count)| leaf_count)
+    }
+
+    /// Verifies that `leaf` sits at `leaf_index`, returning the matched root.
+    /// The index must have been appended when that root was current, since
+    /// empty slots would otherwise prove as zero leaves.
+    pub fn verify(&self, leaf: [u8; 32], leaf_index: u64, proof: &[[u8; 32]]) -> Option<[u8; 32]> {
+        if leaf_index >= self.next_index || proof.len() != MERKLE_TREE_DEPTH {
+            return None;
+        }
+
+        let root = compute_root(leaf, leaf_index, proof);
+        let leaf_count = self.leaf_count_at(&root)?;
+        (leaf_index < leaf_count).then_some(root)
+    }
+}
+
+impl Default for FingerprintTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn compute_root(leaf: [u8; 32], leaf_index: u64, proof: &[[u8; 32]]) -> [u8; 32] {
+    let mut index = leaf_index;
+    let mut current = leaf;
+
+    for sibling in proof {
+        current = if index & 1 == 0 {
+            hash_pair(&current, sibling)
+        } else {
+            hash_pair(sibling, &current)
+        };
+        index >>= 1;
+    }
+
+    current
+}
+
+pub fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hashv(&[left, right]).to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: u64) -> [u8; 32] {
+        hashv(&[&i.to_le_bytes()]).to_bytes()
+    }
+
+    /// Sibling path for `index`, recomputed from all leaves like an off-chain indexer would.
+    fn proof(leaves: &[[u8; 32]], mut index: usize) -> Vec<[u8; 32]> {
+        let mut level = leaves.to_vec();
+        let mut zero = [0u8; 32];
+        let mut proof = Vec::with_capacity(MERKLE_TREE_DEPTH);
+
+        for _ in 0..MERKLE_TREE_DEPTH {
+            proof.push(level.get(index ^ 1).copied().unwrap_or(zero));
+            level = level
+                .chunks(2)
+                .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&zero)))
+                .collect();
+            zero = hash_pair(&zero, &zero);
+            index >>= 1;
+        }
+
+        proof
+    }
+
+    fn tree_with(count: u64) -> (FingerprintTree, Vec<[u8; 32]>) {
+        let mut tree = FingerprintTree::new();
+        let leaves: Vec<[u8; 32]> = (0..count).map(leaf).collect();
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(tree.append(*leaf).unwrap(), i as u64);
+        }
+        (tree, leaves)
+    }
+
+    #[test]
+    fn append_matches_recomputed_root() {
+        let (tree, leaves) = tree_with(5);
+        let root = compute_root(leaves[3], 3, &proof(&leaves, 3));
+
+        assert_eq!(tree.next_index, 5);
+        assert_eq!(tree.root(), root);
+    }
+
+    #[test]
+    fn verify_accepts_every_leaf() {
+        let (tree, leaves) = tree_with(7);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(
+                tree.verify(*leaf, i as u64, &proof(&leaves, i)),
+                Some(tree.root())
+            );
+        }
+    }
+
+    #[test]
+    fn verify_rejects_wrong_leaf() {
+        let (tree, leaves) = tree_with(4);
+
+        assert_eq!(tree.verify(leaf(99), 2, &proof(&leaves, 2)), None);
+    }
+
+    #[test]
+    fn verify_rejects_wrong_proof_length() {
+        let (tree, leaves) = tree_with(4);
+        let mut short_proof = proof(&leaves, 1);
+        short_proof.pop();
+
+        assert_eq!(tree.verify(leaves[1], 1, &short_proof), None);
+    }
+
+    #[test]
+    fn verify_rejects_index_past_next_index() {
+        let (tree, leaves) = tree_with(4);
+        let mut padded = leaves.clone();
+        padded.push([0u8; 32]);
+
+        // The empty slot hashes to the current root, but was never appended
+        assert_eq!(tree.verify([0u8; 32], 4, &proof(&padded, 4)), None);
+    }
+
+    #[test]
+    fn verify_rejects_index_past_leaf_count_of_history_root() {
+        let (mut tree, leaves) = tree_with(4);
+        let mut padded = leaves.clone();
+        padded.push([0u8; 32]);
+        let empty_slot_proof = proof(&padded, 4);
+
+        // Slot 4 was still empty in the previous root, which stays in the history
+        tree.append(leaf(4)).unwrap();
+        assert_eq!(tree.verify([0u8; 32], 4, &empty_slot_proof), None);
+        assert_eq!(
+            tree.leaf_count_at(&compute_root([0u8; 32], 4, &empty_slot_proof)),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn verify_accepts_recent_roots_and_rejects_stale_ones() {
+        let (mut tree, mut leaves) = tree_with(1);
+        let first_proof = proof(&leaves, 0);
+
+        // Proofs against recent roots keep verifying while the history holds them
+        for i in 1..MERKLE_ROOT_HISTORY_SIZE as u64 {
+            leaves.push(leaf(i));
+            tree.append(leaf(i)).unwrap();
+        }
+        assert!(tree.verify(leaves[0], 0, &first_proof).is_some());
+
+        leaves.push(leaf(MERKLE_ROOT_HISTORY_SIZE as u64));
+        tree.append(leaf(MERKLE_ROOT_HISTORY_SIZE as u64)).unwrap();
+        assert_eq!(tree.verify(leaves[0], 0, &first_proof), None);
+        assert!(tree.verify(leaves[0], 0, &proof(&leaves, 0)).is_some());
+    }
+
+    #[test]
+    fn empty_tree_knows_no_root() {
+        let tree = FingerprintTree::new();
+
+        assert_eq!(tree.leaf_count_at(&tree.root()), None);
+        assert_eq!(tree.leaf_count_at(&[0u8; 32]), None);
+        assert_eq!(tree.verify(leaf(0), 0, &proof(&[], 0)), None);
+    }
+}
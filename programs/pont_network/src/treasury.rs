@@ -0,0 +1,298 @@
+use anchor_lang::prelude::*;
+
+use crate::config::ProtocolConfig;
+use crate::fundraising::FundraisingRound;
+
+pub const MAX_TREASURY_SIGNERS: usize = 10;
+
+/// M-of-N signer set allowed to move raised funds out of successful rounds.
+///
+/// The treasury only ever draws from `FundraisingRound` accounts, never from
+/// the fundraising account, so fees owed to stakers cannot be withdrawn.
+#[account]
+pub struct Treasury {
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub proposal_count: u64,
+    pub total_withdrawn: u64,
+}
+
+impl Treasury {
+    pub const SIZE: usize = 4 + MAX_TREASURY_SIGNERS * 32 + 1 + 8 + 8;
+
+    pub fn require_signer(&self, key: &Pubkey) -> Result<()> {
+        require!(
+            self.signers.contains(key),
+            TreasuryErrors::NotTreasurySigner
+        );
+        Ok(())
+    }
+}
+
+#[account]
+pub struct WithdrawalProposal {
+    pub index: u64,
+    pub round: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub approvals: Vec<Pubkey>,
+    pub executed: bool,
+}
+
+impl WithdrawalProposal {
+    pub const SIZE: usize = 8 + 32 + 32 + 8 + 4 + MAX_TREASURY_SIGNERS * 32 + 1;
+}
+
+#[derive(Accounts)]
+pub struct InitializeTreasury<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(
+        init,
+        seeds = [b"treasury"],
+        bump,
+        payer = admin,
+        space = 8 + Treasury::SIZE
+    )]
+    pub treasury: Account<'info, Treasury>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeWithdrawal<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        init,
+        seeds = [b"withdrawal_proposal", treasury.proposal_count.to_le_bytes().as_ref()],
+        bump,
+        payer = proposer,
+        space = 8 + WithdrawalProposal::SIZE
+    )]
+    pub proposal: Account<'info, WithdrawalProposal>,
+    #[account(
+        seeds = [b"fundraising_round", fundraising_round.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveWithdrawal<'info> {
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"treasury"], bump)]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"withdrawal_proposal", proposal.index.to_le_bytes().as_ref()],
+        bump,
+        constraint = !proposal.executed @ TreasuryErrors::ProposalAlreadyExecuted
+    )]
+    pub proposal: Account<'info, WithdrawalProposal>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteWithdrawal<'info> {
+    pub signer: Signer<'info>,
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: Account<'info, Treasury>,
+    #[account(
+        mut,
+        seeds = [b"withdrawal_proposal", proposal.index.to_le_bytes().as_ref()],
+        bump,
+        has_one = destination,
+        constraint = !proposal.executed @ TreasuryErrors::ProposalAlreadyExecuted,
+        constraint = proposal.round == fundraising_round.key() @ TreasuryErrors::RoundMismatch
+    )]
+    pub proposal: Account<'info, WithdrawalProposal>,
+    #[account(
+        mut,
+        seeds = [b"fundraising_round", fundraising_round.index.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub fundraising_round: Account<'info, FundraisingRound>,
+    #[account(mut)]
+    pub destination: SystemAccount<'info>,
+}
+
+pub fn initialize_treasury(
+    ctx: Context<InitializeTreasury>,
+    signers: Vec<Pubkey>,
+    threshold: u8,
+) -> Result<()> {
+    require!(
+        !signers.is_empty() && signers.len() <= MAX_TREASURY_SIGNERS,
+        TreasuryErrors::InvalidSignerSet
+    );
+    require!(
+        threshold > 0 && threshold as usize <= signers.len(),
+        TreasuryErrors::InvalidThreshold
+    );
+    for (i, signer) in signers.iter().enumerate() {
+        require!(
+            !signers[..i].contains(signer),
+            TreasuryErrors::InvalidSignerSet
+        );
+    }
+
+    let treasury = &mut ctx.accounts.treasury;
+    treasury.signers = signers.clone();
+    treasury.threshold = threshold;
+    treasury.proposal_count = 0;
+    treasury.total_withdrawn = 0;
+
+    emit!(TreasuryInitialized { signers, threshold });
+
+    Ok(())
+}
+
+/// Proposes moving `amount` lamports raised by `fundraising_round` to
+/// `destination`. The proposer's approval is counted right away.
+pub fn propose_withdrawal(
+    ctx: Context<ProposeWithdrawal>,
+    destination: Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let treasury = &mut ctx.accounts.treasury;
+    let proposer = ctx.accounts.proposer.key();
+    treasury.require_signer(&proposer)?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    proposal.index = treasury.proposal_count;
+    proposal.round = ctx.accounts.fundraising_round.key();
+    proposal.destination = destination;
+    proposal.amount = amount;
+    proposal.approvals = vec![proposer];
+    proposal.executed = false;
+
+    treasury.proposal_count += 1;
+
+    emit!(WithdrawalProposed {
+        proposal: proposal.key(),
+        index: proposal.index,
+        proposer,
+        round: proposal.round,
+        destination,
+        amount,
+    });
+
+    Ok(())
+}
+
+pub fn approve_withdrawal(ctx: Context<ApproveWithdrawal>) -> Result<()> {
+    let signer = ctx.accounts.signer.key();
+    ctx.accounts.treasury.require_signer(&signer)?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    require!(
+        !proposal.approvals.contains(&signer),
+        TreasuryErrors::AlreadyApproved
+    );
+    proposal.approvals.push(signer);
+
+    emit!(WithdrawalApproved {
+        proposal: proposal.key(),
+        signer,
+        approvals: proposal.approvals.len() as u8,
+    });
+
+    Ok(())
+}
+
+/// Pays out an approved proposal from its round, once the round has ended at
+/// or above its soft cap.
+pub fn execute_withdrawal(ctx: Context<ExecuteWithdrawal>) -> Result<()> {
+    let treasury = &mut ctx.accounts.treasury;
+    treasury.require_signer(&ctx.accounts.signer.key())?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    require!(
+        proposal.approvals.len() >= treasury.threshold as usize,
+        TreasuryErrors::NotEnoughApprovals
+    );
+
+    let fundraising_round = &ctx.accounts.fundraising_round;
+    require!(
+        fundraising_round.succeeded(Clock::get()?.unix_timestamp),
+        TreasuryErrors::RoundNotSuccessful
+    );
+
+    let round_info = fundraising_round.to_account_info();
+    let available = round_info
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(round_info.data_len()));
+    require!(
+        proposal.amount <= available,
+        TreasuryErrors::InsufficientTreasuryFunds
+    );
+
+    **round_info.try_borrow_mut_lamports()? -= proposal.amount;
+    **ctx
+        .accounts
+        .destination
+        .to_account_info()
+        .try_borrow_mut_lamports()? += proposal.amount;
+
+    proposal.executed = true;
+    treasury.total_withdrawn += proposal.amount;
+
+    emit!(WithdrawalExecuted {
+        proposal: proposal.key(),
+        round: proposal.round,
+        destination: proposal.destination,
+        amount: proposal.amount,
+        total_withdrawn: treasury.total_withdrawn,
+    });
+
+    Ok(())
+}
+
+#[event]
+pub struct TreasuryInitialized {
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+}
+
+#[event]
+pub struct WithdrawalProposed {
+    pub proposal: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub round: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WithdrawalApproved {
+    pub proposal: Pubkey,
+    pub signer: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct WithdrawalExecuted {
+    pub proposal: Pubkey,
+    pub round: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub total_withdrawn: u64,
+}
+
+#[error_code(offset = 6400)]
+pub enum TreasuryErrors {
+    InvalidSignerSet,
+    InvalidThreshold,
+    NotTreasurySigner,
+    AlreadyApproved,
+    ProposalAlreadyExecuted,
+    NotEnoughApprovals,
+    RoundMismatch,
+    RoundNotSuccessful,
+    InsufficientTreasuryFunds,
+}
@@ -0,0 +1,51 @@
+import { blake3 } from 'hash-wasm'
+
+// Must match MERKLE_TREE_DEPTH in programs/pont_network/src/merkle.rs
+export const MERKLE_TREE_DEPTH = 20;
+
+async function hashPair(left: Buffer, right: Buffer): Promise<Buffer> {
+	return Buffer.from(await blake3(Buffer.concat([left, right])), 'hex');
+}
+
+// Recomputes the root the program keeps for the given fingerprints (leaves in insertion order)
+export async function merkleRoot(leaves: Buffer[]): Promise<Buffer> {
+	let level = leaves;
+	let zero = Buffer.alloc(32);
+
+	for (let depth = 0; depth < MERKLE_TREE_DEPTH; depth++) {
+		const nextLevel = [];
+		for (let i = 0; i < level.length; i += 2) {
+			nextLevel.push(await hashPair(level[i], i + 1 < level.length ? level[i + 1] : zero));
+		}
+		level = nextLevel;
+		zero = await hashPair(zero, zero);
+	}
+
+	return level.length > 0 ? level[0] : zero;
+}
+
+// Sibling path from the leaf at `index` up to the root, as expected by `verify_fingerprint`
+export async function merkleProof(leaves: Buffer[], index: number): Promise<Buffer[]> {
+	const proof = [];
+	let level = leaves;
+	let zero = Buffer.alloc(32);
+
+	for (let depth = 0; depth < MERKLE_TREE_DEPTH; depth++) {
+		const sibling = index ^ 1;
+		proof.push(sibling < level.length ? level[sibling] : zero);
+
+		const nextLevel = [];
+		for (let i = 0; i < level.length; i += 2) {
+			nextLevel.push(await hashPair(level[i], i + 1 < level.length ? level[i + 1] : zero));
+		}
+		level = nextLevel;
+		zero = await hashPair(zero, zero);
+		index >>= 1;
+	}
+
+	return proof;
+}
+
+export function currentRoot(fingerprintTree: { roots: number[][], currentRootIndex: { toNumber(): number } }): Buffer {
+	return Buffer.from(fingerprintTree.roots[fingerprintTree.currentRootIndex.toNumber()]);
+}
@@ -20,6 +20,9 @@ describe("pont_network", () => {
 	console.log("Provider: ", anchor.getProvider());
 	// cosnt program = new Program
 	const program = anchor.workspace.PontNetwork as Program<PontNetwork>;
+
+	const [fundraisingAccount] = PublicKey.findProgramAddressSync([Buffer.from("fundraising")], program.programId);
+	const [protocolConfig] = PublicKey.findProgramAddressSync([Buffer.from("protocol_config")], program.programId);
 	
 	const ship2 = anchor.web3.Keypair.generate();
 	const ship3 = anchor.web3.Keypair.generate();
@@ -159,6 +162,8 @@ describe("pont_network", () => {
 				systemProgram: SystemProgram.programId,
 				dataAccount: dataAccount1,
 				externalObserversAccount: externalObserversAccount1,
+				fundraisingAccount,
+				protocolConfig,
 			})
 			.signers([ship1])
 			.rpc();
@@ -182,253 +187,12 @@ describe("pont_network", () => {
 				systemProgram: SystemProgram.programId,
 				dataAccount: dataAccount2,
 				externalObserversAccount: externalObserversAccount2,
+				fundraisingAccount,
+				protocolConfig,
 			})
 			.signers([ship1])
 			.rpc();
 	});
-
-	const eo3 = anchor.web3.Keypair.generate();
-	const eo3_x25519pk = x25519.getPublicKey(eo3.secretKey.slice(0, 32));
-
-	it("Requests to be an External Observer", async () => {
-		const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
-			[Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
-			program.programId
-		);
-
-		const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
-
-		const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
-			[Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
-			program.programId
-		);
-
-		const [externalObserversAccount, bump3] = PublicKey.findProgramAddressSync(
-			[Buffer.from("external_observers_account"), dataAccount.toBuffer()],
-			program.programId
-		);
-
-		// Fund the external observer account
-		const airdropSignature = await program.provider.connection.requestAirdrop(
-			eo3.publicKey,
-			anchor.web3.LAMPORTS_PER_SOL
-		);
-
-		const latestBlockHash = await program.provider.connection.getLatestBlockhash();
-
-		await program.provider.connection.confirmTransaction({
-			blockhash: latestBlockHash.blockhash,
-			lastValidBlockHeight: latestBlockHash.lastValidBlockHeight,
-			signature: airdropSignature,
-		})
-
-		const tx = await program.methods
-			.externalObserverRequest(new PublicKey(eo3_x25519pk))
-			.accountsStrict({
-				dataAccount,
-				externalObserversAccount,
-				externalObserver: eo3.publicKey,
-				systemProgram: SystemProgram.programId,
-			})
-			.signers([eo3])
-			.rpc();
-
-		console.log("External Observer requested with transaction signature", tx);
-
-		const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
-
-		// Convert PublicKey objects to strings for comparison
-		const unapprovedExternalObservers = account.unapprovedExternalObservers.map((pk: PublicKey) => pk.toString());
-		const externalObserverPublicKey = eo3.publicKey.toString();
-
-		expect(unapprovedExternalObservers).to.include(externalObserverPublicKey);
-	});
-
-	it("Approves an External Observer", async () => {
-		const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
-			[Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
-			program.programId
-		);
-		const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
-
-		const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
-			[Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
-			program.programId
-		);
-
-		const [externalObserversAccount, bump3] = PublicKey.findProgramAddressSync(
-			[Buffer.from("external_observers_account"), dataAccount.toBuffer()],
-			program.programId
-		);
-
-		const accountPreTx = await program.account.externalObserversAccount.fetch(externalObserversAccount);
-		const externalObserverIndex = accountPreTx.unapprovedExternalObservers.findIndex((pk: PublicKey) => pk.equals(eo3.publicKey));
-		const eo_x25519_pk = accountPreTx.unapprovedExternalObserversX25519Pks[externalObserverIndex];
-
-		const encryptedExternalObserverKey = await ecies25519.encrypt(keyBytes, eo_x25519_pk.toBytes())
-
-		// Approve the external observer
-		const tx = await program.methods
-			.addExternalObserver(eo3.publicKey, Array.from(encryptedExternalObserverKey))
-			.accountsStrict({
-				externalObserversAccount,
-				dataAccount,
-				shipAccount: shipAccountAddress,
-				shipManagement: shipManagement.publicKey,
-				systemProgram: SystemProgram.programId,
-			}).
-			signers([shipManagement])
-			.rpc();
-
-		console.log("External Observer approved with transaction signature", tx);
-
-		const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
-
-		// Convert PublicKey objects to strings for comparison
-		const unapprovedExternalObservers = account.unapprovedExternalObservers.map((pk: PublicKey) => pk.toString());
-		const externalObservers = account.externalObservers.map((pk: PublicKey) => pk.toString());
-		const externalObserverPublicKey = eo3.publicKey.toString();
-		const externalObserversEncryptedKeys = account.externalObserversMasterKeys.map((key: number[]) => Uint8Array.from(key));
-
-		expect(unapprovedExternalObservers).to.not.include(externalObserverPublicKey);
-		expect(externalObservers).to.include(externalObserverPublicKey);
-
-		// Decrypt the external observer key
-		const decryptedExternalObserverKey = await ecies25519.decrypt(externalObserversEncryptedKeys[0], eo3.secretKey.slice(0, 32));
-		const decryptedExternalObserverKeyBuffer = Buffer.from(decryptedExternalObserverKey);
-		expect(decryptedExternalObserverKeyBuffer).to.deep.equal(keyBytes);
-	});
-
-	const sensorData = {
-		"lat": -4.96579,
-		"long": -1.72182,
-		"mileage": 0.25,
-		"engineLoad": 79.81,
-		"fuelLevel": 99.89,
-		"seaState": "high",
-		"seaSurfaceTemperature": 11.7,
-		"airTemp": 25.6,
-		"humidity": 58.22,
-		"barometricPressure": 999.71,
-		"cargoStatus": "INTRANSIT",
-		"time": 1725629220025
-	};
-	const sensorDataJson = JSON.stringify(sensorData);
-	const sensorDataBuffer = Buffer.from(sensorDataJson);
-	
-	it("Adds a Data Fingerprint", async () => {
-		const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
-			[Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
-			program.programId
-		);
-		const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
-
-		const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
-			[Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
-			program.programId
-		);
-
-		const iv = new Uint32Array(3);
-		crypto.getRandomValues(iv);
-
-		const encryptedData = encrypt(sensorDataBuffer, masterKey, iv);
-		const ciphertext = encryptedData.ciphertext;
-		const tag = encryptedData.tag;
-		console.log("Encrypted Data: ", encryptedData);
-
-		const serializedEncryptedData = serializeEncryptedData(encryptedData);
-		const ciphertextBuffer = serializedEncryptedData.ciphertext;
-		const tagBuffer = serializedEncryptedData.tag;
-		const ivBuffer = serializedEncryptedData.iv;
-		console.log("\nSerialized Encrypted Data: ", serializedEncryptedData);
-
-		const dataFingerprint = await blake3(sensorDataBuffer);
-		const encryptedDataFingerprint = await blake3(ciphertextBuffer);
-		const dataTimestamp = Date.now();
-
-		const tx = await program.methods
-			.addDataFingerprint(ciphertextBuffer, tagBuffer, ivBuffer, new anchor.BN(dataTimestamp))
-			.accountsStrict({
-				dataAccount,
-				ship: ship1.publicKey,
-			})
-			.signers([ship1])
-			.rpc();
-
-		console.log("Data Fingerprint added with transaction signature", tx);
-
-		const txDetails = await program.provider.connection.getTransaction(tx, {
-			maxSupportedTransactionVersion: 0,
-			commitment: "confirmed",
-		});
-
-		console.log("Transaction Details: ", txDetails);
-
-		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(1);
-
-		// Convert byte array (number[]) to Buffer
-		const fingerprintBuffer = Buffer.from(account.fingerprints[0][0]);
-
-		// Convert Buffer to hex string
-		const fingerprintHex = fingerprintBuffer.toString('hex');
-		console.log("Data Fingerprint: ", fingerprintHex);
-
-		expect(fingerprintHex).to.equal(encryptedDataFingerprint);
-	});
-
-	it("Adds Multiple Data Fingerprints", async () => {
-		const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
-			[Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
-			program.programId
-		);
-		const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
-
-		const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
-			[Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
-			program.programId
-		);
-
-		const data = [sensorDataBuffer, sensorDataBuffer, sensorDataBuffer];
-
-		const ivs = [new Uint32Array(3), new Uint32Array(3), new Uint32Array(3)];
-		crypto.getRandomValues(ivs[0]);
-		crypto.getRandomValues(ivs[1]);
-		crypto.getRandomValues(ivs[2]);
-
-		const encryptedData = [encrypt(data[0], masterKey, ivs[0]), encrypt(data[1], masterKey, ivs[1]), encrypt(data[2], masterKey, ivs[2])];
-		const ciphertexts = [encryptedData[0].ciphertext, encryptedData[1].ciphertext, encryptedData[2].ciphertext];
-		const tags = [encryptedData[0].tag, encryptedData[1].tag, encryptedData[2].tag];
-		console.log("Encrypted Data: ", encryptedData);
-
-		const serializedEncryptedData = [serializeEncryptedData(encryptedData[0]), serializeEncryptedData(encryptedData[1]), serializeEncryptedData(encryptedData[2])];
-		const ciphertextBuffers = [serializedEncryptedData[0].ciphertext, serializedEncryptedData[1].ciphertext, serializedEncryptedData[2].ciphertext];
-		const tagBuffers = [serializedEncryptedData[0].tag, serializedEncryptedData[1].tag, serializedEncryptedData[2].tag];
-		const ivBuffers = [serializedEncryptedData[0].iv, serializedEncryptedData[1].iv, serializedEncryptedData[2].iv];
-		console.log("\nSerialized Encrypted Data: ", serializedEncryptedData);
-
-		// const dataFingerprints = await blake3(data);
-		const encryptedDataFingerprint = [await blake3(ciphertextBuffers[0]), await blake3(ciphertextBuffers[1]), await blake3(ciphertextBuffers[2])];
-
-		const dataTimestamps = [Date.now(), Date.now() + 1000, Date.now() + 2000];
-
-		const tx = await program.methods
-			.addMultipleDataFingerprints(ciphertextBuffers, tagBuffers, ivBuffers, dataTimestamps.map(ts => new anchor.BN(ts)))
-			.accountsStrict({
-				dataAccount,
-				ship: ship1.publicKey,
-			})
-			.signers([ship1])
-			.rpc();
-
-		console.log("Data Fingerprint added with transaction signature", tx);
-
-		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(4);
-		expect(Buffer.from(account.fingerprints[1][0]).toString('hex')).to.equal(encryptedDataFingerprint[0]);
-		expect(Buffer.from(account.fingerprints[2][0]).toString('hex')).to.equal(encryptedDataFingerprint[1]);
-		expect(Buffer.from(account.fingerprints[3][0]).toString('hex')).to.equal(encryptedDataFingerprint[2]);
-	});
 });
 
 // Encrypt data
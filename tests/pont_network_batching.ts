@@ -8,8 +8,9 @@ import crypto from 'crypto';
 import * as ecies25519 from 'ecies-25519';
 import * as encUtils from 'enc-utils';
 import { x25519 } from '@noble/curves/ed25519'
+import { merkleRoot, merkleProof, currentRoot } from './merkle'
 import { TOKEN_PROGRAM_ID } from "@coral-xyz/anchor/dist/cjs/utils/token";
-import { getAssociatedTokenAddress, getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { createMint, getAccount, getAssociatedTokenAddress, getAssociatedTokenAddressSync, getOrCreateAssociatedTokenAccount, mintTo, transfer } from "@solana/spl-token";
 
 describe("pont_network", () => {
     const ship1 = anchor.web3.Keypair.generate();
@@ -23,6 +24,26 @@ describe("pont_network", () => {
     // cosnt program = new Program
     const program = anchor.workspace.PontNetwork as Program<PontNetwork>;
 
+    const [fundraisingAccount] = PublicKey.findProgramAddressSync([Buffer.from("fundraising")], program.programId);
+    const [protocolConfig] = PublicKey.findProgramAddressSync([Buffer.from("protocol_config")], program.programId);
+    const [programData] = PublicKey.findProgramAddressSync([program.programId.toBuffer()], new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111"));
+    // Upgrade authority of the deployed program, the only key allowed to initialize the config
+    const deployer = Wallet.local().payer;
+    const SEED_ROUND_DURATION = 20; // seconds
+    const [seedRound] = PublicKey.findProgramAddressSync([Buffer.from("fundraising_round"), new anchor.BN(0).toArrayLike(Buffer, "le", 8)], program.programId);
+    const REWARD_PRECISION = new anchor.BN(10).pow(new anchor.BN(18));
+
+    // Mirrors the on-chain accumulator, which rounds every fee down on its own
+    function rewardsFor(weightedStake: anchor.BN, fees: anchor.BN[], totalStaked: anchor.BN): number {
+        const rewardPerToken = fees.reduce((sum, fee) => sum.add(fee.mul(REWARD_PRECISION).div(totalStaked)), new anchor.BN(0));
+        return weightedStake.mul(rewardPerToken).div(REWARD_PRECISION).toNumber();
+    }
+
+    // Rewards a stake has earned but not been paid yet
+    function pendingRewards(userStake: { weightedStake: anchor.BN, rewardDebt: anchor.BN, unclaimedRewards: anchor.BN }, rewardPerToken: anchor.BN): number {
+        return userStake.weightedStake.mul(rewardPerToken).div(REWARD_PRECISION).sub(userStake.rewardDebt).add(userStake.unclaimedRewards).toNumber();
+    }
+
     const ship2 = anchor.web3.Keypair.generate();
     const ship3 = anchor.web3.Keypair.generate();
     const ship4 = anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(99));
@@ -75,6 +96,110 @@ describe("pont_network", () => {
         await airdropLamports(vc3.publicKey, 1000 * LAMPORTS_PER_SOL);
     });
 
+    it("Initializes the protocol config", async () => {
+        const feeSchedule = {
+            fingerprintFee: new anchor.BN(0.01 * LAMPORTS_PER_SOL),
+            batchFee: new anchor.BN(0),
+            dataAccountFee: new anchor.BN(0),
+            observerApprovalFee: new anchor.BN(0),
+            volumeDiscountThreshold: 0,
+            volumeDiscountBps: 0,
+            creditPrice: new anchor.BN(0),
+        };
+
+        try {
+            await program.methods.initializeProtocolConfig(feeSchedule).accountsPartial({
+                admin: shipManagement.publicKey,
+                program: program.programId,
+                programData,
+            }).signers([shipManagement]).rpc();
+            expect.fail("initialized the config without the upgrade authority");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("NotUpgradeAuthority");
+        }
+
+        await program.methods.initializeProtocolConfig(feeSchedule).accountsPartial({
+            admin: deployer.publicKey,
+            program: program.programId,
+            programData,
+        }).signers([deployer]).rpc();
+
+        await program.methods.setProtocolAdmin(shipManagement.publicKey).accounts({
+            admin: deployer.publicKey,
+        }).signers([deployer]).rpc();
+
+        const config = await program.account.protocolConfig.fetch(protocolConfig);
+        expect(config.admin.toBase58()).to.equal(shipManagement.publicKey.toBase58());
+        expect(config.feeSchedule.fingerprintFee.toNumber()).to.equal(0.01 * LAMPORTS_PER_SOL);
+    });
+
+    it("Fundraising", async () => {
+        await program.methods.initializeFundraising(
+            "Pont Network Shares",
+            "PNTSH",
+            "https://example.com",
+        ).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        try {
+            await program.methods.startFundraisingRound(
+                { seed: {} },
+                new anchor.BN(SEED_ROUND_DURATION),
+                new anchor.BN(1000 * LAMPORTS_PER_SOL),
+                new anchor.BN(100 * LAMPORTS_PER_SOL),
+                new anchor.BN(LAMPORTS_PER_SOL),
+                new anchor.BN(0),
+                new anchor.BN(0),
+            ).accountsPartial({
+                admin: vc1.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc1]).rpc();
+            expect.fail("a non-admin started a round");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("ConstraintHasOne");
+        }
+        expect((await program.account.fundraisingAccount.fetch(fundraisingAccount)).roundCount.toNumber()).to.equal(0);
+
+        // Short round without cliff or vesting, so shares can be released and staked below
+        await program.methods.startFundraisingRound(
+            { seed: {} },
+            new anchor.BN(SEED_ROUND_DURATION),
+            new anchor.BN(1000 * LAMPORTS_PER_SOL),
+            new anchor.BN(100 * LAMPORTS_PER_SOL),
+            new anchor.BN(LAMPORTS_PER_SOL),
+            new anchor.BN(0),
+            new anchor.BN(0),
+        ).accountsPartial({
+            admin: shipManagement.publicKey,
+            fundraisingRound: seedRound,
+        }).signers([shipManagement]).rpc();
+
+        const round = await program.account.fundraisingRound.fetch(seedRound);
+        expect(round.hardCap.toNumber()).to.equal(1000 * LAMPORTS_PER_SOL);
+        expect(round.softCap.toNumber()).to.equal(100 * LAMPORTS_PER_SOL);
+        expect(round.endTime.toNumber() - round.startTime.toNumber()).to.equal(SEED_ROUND_DURATION);
+        expect((await program.account.fundraisingAccount.fetch(fundraisingAccount)).roundCount.toNumber()).to.equal(1);
+
+        // Rounds are sequential
+        try {
+            await program.methods.startFundraisingRound(
+                { private: {} },
+                new anchor.BN(7 * 24 * 60 * 60),
+                new anchor.BN(1000 * LAMPORTS_PER_SOL),
+                new anchor.BN(100 * LAMPORTS_PER_SOL),
+                new anchor.BN(2 * LAMPORTS_PER_SOL),
+                new anchor.BN(30 * 24 * 60 * 60),
+                new anchor.BN(365 * 24 * 60 * 60),
+            ).accounts({
+                admin: shipManagement.publicKey,
+            }).signers([shipManagement]).rpc();
+            expect.fail("second round started while the first is active");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("PreviousRoundActive");
+        }
+    });
+
     it("Initializes a ShipAccounts", async () => {
         const [shipAccount1] = PublicKey.findProgramAddressSync(
             [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
@@ -168,6 +293,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount1,
                 externalObserversAccount: externalObserversAccount1,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship1])
             .rpc();
@@ -198,6 +325,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount2,
                 externalObserversAccount: externalObserversAccount2,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship2])
             .rpc();
@@ -228,6 +357,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount3,
                 externalObserversAccount: externalObserversAccount3,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship3])
             .rpc();
@@ -258,6 +389,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount4,
                 externalObserversAccount: externalObserversAccount4,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship4])
             .rpc();
@@ -283,48 +416,269 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount4_2,
                 externalObserversAccount: externalObserversAccount4_2,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship4])
             .rpc();
     });
 
-    it("Fundraising", async () => {
-        await program.methods.startFundraising().accounts({
-            user: shipManagement.publicKey,
-        }).signers([shipManagement]).rpc();
-    });
-
     it("Contribute", async () => {
         const tx1 = await program.methods.contribute(new anchor.BN(100 * LAMPORTS_PER_SOL)).accounts({
-            user: vc1.publicKey
+            user: vc1.publicKey,
+            fundraisingRound: seedRound,
         }).signers([vc1]).rpc();
 
         const tx2 = await program.methods.contribute(new anchor.BN(200 * LAMPORTS_PER_SOL)).accounts({
-            user: vc2.publicKey
+            user: vc2.publicKey,
+            fundraisingRound: seedRound,
         }).signers([vc2]).rpc();
 
         const t3 = await program.methods.contribute(new anchor.BN(300 * LAMPORTS_PER_SOL)).accounts({
-            user: vc3.publicKey
+            user: vc3.publicKey,
+            fundraisingRound: seedRound,
         }).signers([vc3]).rpc();
+
+        // 600 of the 1000 SOL hard cap are raised, so one lamport over 400 SOL is too much
+        try {
+            await program.methods.contribute(new anchor.BN(400 * LAMPORTS_PER_SOL + 1)).accounts({
+                user: vc1.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc1]).rpc();
+            expect.fail("contributed past the hard cap");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("HardCapReached");
+        }
+        expect((await program.account.fundraisingRound.fetch(seedRound)).totalFundsRaised.toNumber()).to.equal(600 * LAMPORTS_PER_SOL);
+    });
+
+    it("Rejects refunds and releases before the round has ended", async () => {
+        const [contribution] = PublicKey.findProgramAddressSync(
+            [Buffer.from("contribution"), seedRound.toBuffer(), vc1.publicKey.toBuffer()],
+            program.programId
+        );
+        expect((await program.account.contribution.fetch(contribution)).amount.toNumber()).to.equal(100 * LAMPORTS_PER_SOL);
+
+        try {
+            await program.methods.refund().accountsPartial({
+                user: vc1.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc1]).rpc();
+            expect.fail("refunded an active round");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("RoundNotEnded");
+        }
+
+        try {
+            await program.methods.releaseVested().accountsPartial({
+                user: vc1.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc1]).rpc();
+            expect.fail("released shares of an active round");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("RoundNotEnded");
+        }
+    });
+
+    it("Releases vested shares once the round has ended", async () => {
+        const round = await program.account.fundraisingRound.fetch(seedRound);
+        const waitMs = (round.endTime.toNumber() + 2) * 1000 - Date.now();
+        if (waitMs > 0) {
+            await new Promise((resolve) => setTimeout(resolve, waitMs));
+        }
+
+        for (const vc of [vc1, vc2, vc3]) {
+            await program.methods.releaseVested().accountsPartial({
+                user: vc.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc]).rpc();
+        }
+
+        const { tokenMint } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const vc3TokenAccount = getAssociatedTokenAddressSync(tokenMint, vc3.publicKey);
+        expect(Number((await getAccount(program.provider.connection, vc3TokenAccount)).amount)).to.equal(300 * LAMPORTS_PER_SOL);
+    });
+
+    it("Withdraws raised funds from the treasury with 2-of-2 approval", async () => {
+        const destination = anchor.web3.Keypair.generate().publicKey;
+        const [proposal] = PublicKey.findProgramAddressSync(
+            [Buffer.from("withdrawal_proposal"), new anchor.BN(0).toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        await program.methods.initializeTreasury([shipManagement.publicKey, vc1.publicKey], 2).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        await program.methods.proposeWithdrawal(destination, new anchor.BN(10 * LAMPORTS_PER_SOL)).accountsPartial({
+            proposer: shipManagement.publicKey,
+            fundraisingRound: seedRound,
+        }).signers([shipManagement]).rpc();
+
+        try {
+            await program.methods.executeWithdrawal().accountsPartial({
+                signer: shipManagement.publicKey,
+                proposal,
+                fundraisingRound: seedRound,
+                destination,
+            }).signers([shipManagement]).rpc();
+            expect.fail("executed with a single approval");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("NotEnoughApprovals");
+        }
+
+        await program.methods.approveWithdrawal().accountsPartial({
+            signer: vc1.publicKey,
+            proposal,
+        }).signers([vc1]).rpc();
+
+        const feeLamportsBefore = await program.provider.connection.getBalance(fundraisingAccount);
+
+        await program.methods.executeWithdrawal().accountsPartial({
+            signer: vc1.publicKey,
+            proposal,
+            fundraisingRound: seedRound,
+            destination,
+        }).signers([vc1]).rpc();
+
+        expect(await program.provider.connection.getBalance(destination)).to.equal(10 * LAMPORTS_PER_SOL);
+        // Fees owed to stakers are never touched
+        expect(await program.provider.connection.getBalance(fundraisingAccount)).to.equal(feeLamportsBefore);
+    });
+
+    it("Rounds share purchases down to whole base units", async () => {
+        const [privateRound] = PublicKey.findProgramAddressSync([Buffer.from("fundraising_round"), new anchor.BN(1).toArrayLike(Buffer, "le", 8)], program.programId);
+
+        // 3 SOL per share, so 1 SOL buys a third of a share
+        await program.methods.startFundraisingRound(
+            { private: {} },
+            new anchor.BN(60 * 60),
+            new anchor.BN(100 * LAMPORTS_PER_SOL),
+            new anchor.BN(0),
+            new anchor.BN(3 * LAMPORTS_PER_SOL),
+            new anchor.BN(0),
+            new anchor.BN(0),
+        ).accountsPartial({
+            admin: shipManagement.publicKey,
+            fundraisingRound: privateRound,
+        }).signers([shipManagement]).rpc();
+
+        await program.methods.contribute(new anchor.BN(LAMPORTS_PER_SOL)).accounts({
+            user: vc1.publicKey,
+            fundraisingRound: privateRound,
+        }).signers([vc1]).rpc();
+
+        const [contribution] = PublicKey.findProgramAddressSync(
+            [Buffer.from("contribution"), privateRound.toBuffer(), vc1.publicKey.toBuffer()],
+            program.programId
+        );
+        const { tokenMint } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const vestingVault = getAssociatedTokenAddressSync(tokenMint, contribution, true);
+
+        // 10^9 * 10^9 / (3 * 10^9) = 333_333_333.33 base units, rounded down
+        expect((await program.account.contribution.fetch(contribution)).tokens.toNumber()).to.equal(333_333_333);
+        expect(Number((await getAccount(program.provider.connection, vestingVault)).amount)).to.equal(333_333_333);
+
+        // A lamport buys less than one base unit and would be kept for nothing
+        try {
+            await program.methods.contribute(new anchor.BN(1)).accounts({
+                user: vc1.publicKey,
+                fundraisingRound: privateRound,
+            }).signers([vc1]).rpc();
+            expect.fail("Contributions too small to buy a base unit should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("ContributionTooSmall");
+        }
+    });
+
+    it("Books contributions to their own round and keeps rounds sequential", async () => {
+        const [privateRound] = PublicKey.findProgramAddressSync([Buffer.from("fundraising_round"), new anchor.BN(1).toArrayLike(Buffer, "le", 8)], program.programId);
+        const contributionTo = (round: PublicKey, user: PublicKey) => PublicKey.findProgramAddressSync(
+            [Buffer.from("contribution"), round.toBuffer(), user.toBuffer()],
+            program.programId
+        )[0];
+
+        try {
+            await program.methods.startFundraisingRound(
+                { public: {} },
+                new anchor.BN(60 * 60),
+                new anchor.BN(100 * LAMPORTS_PER_SOL),
+                new anchor.BN(0),
+                new anchor.BN(LAMPORTS_PER_SOL),
+                new anchor.BN(0),
+                new anchor.BN(0),
+            ).accounts({
+                admin: shipManagement.publicKey,
+            }).signers([shipManagement]).rpc();
+            expect.fail("third round started while the second is active");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("PreviousRoundActive");
+        }
+        expect((await program.account.fundraisingAccount.fetch(fundraisingAccount)).roundCount.toNumber()).to.equal(2);
+
+        try {
+            await program.methods.contribute(new anchor.BN(LAMPORTS_PER_SOL)).accounts({
+                user: vc2.publicKey,
+                fundraisingRound: seedRound,
+            }).signers([vc2]).rpc();
+            expect.fail("contributed to a round that has ended");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("FundraisingPeriodEnded");
+        }
+
+        await program.methods.contribute(new anchor.BN(2 * LAMPORTS_PER_SOL)).accounts({
+            user: vc2.publicKey,
+            fundraisingRound: privateRound,
+        }).signers([vc2]).rpc();
+
+        const privateContribution = await program.account.contribution.fetch(contributionTo(privateRound, vc2.publicKey));
+        expect(privateContribution.round.toBase58()).to.equal(privateRound.toBase58());
+        expect(privateContribution.amount.toNumber()).to.equal(2 * LAMPORTS_PER_SOL);
+        expect(privateContribution.tokens.toNumber()).to.equal(666_666_666);
+        expect((await program.account.fundraisingRound.fetch(privateRound)).totalFundsRaised.toNumber()).to.equal(3 * LAMPORTS_PER_SOL);
+
+        // The seed round and vc2's contribution to it are untouched
+        expect((await program.account.contribution.fetch(contributionTo(seedRound, vc2.publicKey))).amount.toNumber()).to.equal(200 * LAMPORTS_PER_SOL);
+        expect((await program.account.fundraisingRound.fetch(seedRound)).totalFundsRaised.toNumber()).to.equal(600 * LAMPORTS_PER_SOL);
     });
 
     it("Stake", async () => {
-        const tx1 = await program.methods.stake(new anchor.BN(100 * LAMPORTS_PER_SOL)).accounts({
+        const fundraisingSize = (await program.provider.connection.getAccountInfo(fundraisingAccount)).data.length;
+
+        // A single base unit staked alone would take a whole fee per unit
+        try {
+            await program.methods.stake(new anchor.BN(1), new anchor.BN(0)).accounts({
+                sender: vc1.publicKey,
+            }).signers([vc1]).rpc();
+            expect.fail("Stakes below a whole share should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("StakeTooSmall");
+        }
+
+        const tx1 = await program.methods.stake(new anchor.BN(100 * LAMPORTS_PER_SOL), new anchor.BN(0)).accounts({
             sender: vc1.publicKey,
-            recipient: vc1.publicKey
         }).signers([vc1]).rpc();
 
-        const tx2 = await program.methods.stake(new anchor.BN(200 * LAMPORTS_PER_SOL)).accounts({
+        const tx2 = await program.methods.stake(new anchor.BN(200 * LAMPORTS_PER_SOL), new anchor.BN(0)).accounts({
             sender: vc2.publicKey,
-            recipient: vc2.publicKey
         }).signers([vc2]).rpc();
 
-        const tx3 = await program.methods.stake(new anchor.BN(300 * LAMPORTS_PER_SOL)).accounts({
+        const tx3 = await program.methods.stake(new anchor.BN(300 * LAMPORTS_PER_SOL), new anchor.BN(0)).accounts({
             sender: vc3.publicKey,
-            recipient: vc3.publicKey
         }).signers([vc3]).rpc();
 
-        console.log("Test: ", (await program.account.fundraisingAccount.all())[0].account.userStakingInfo);
+        const [vc3Stake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), vc3.publicKey.toBuffer()],
+            program.programId
+        );
+        const userStake = await program.account.userAccount.fetch(vc3Stake);
+        expect(userStake.key.toBase58()).to.equal(vc3.publicKey.toBase58());
+        expect(userStake.amountStaked.toNumber()).to.equal(300 * LAMPORTS_PER_SOL);
+
+        const fundraising = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        expect(fundraising.totalStaked.toNumber()).to.equal(600 * LAMPORTS_PER_SOL);
+        // Stakes live in their own PDAs and never grow the shared account
+        expect((await program.provider.connection.getAccountInfo(fundraisingAccount)).data.length).to.equal(fundraisingSize);
     });
 
     it("Populate Data Fingerprints for first Data Account", async () => {
@@ -382,7 +736,7 @@ describe("pont_network", () => {
         }
 
         const account = await program.account.dataAccount.fetch(dataAccount);
-        expect(account.fingerprints.length).to.equal(10);
+        expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(10);
     });
 
     it("Claim rewards", async () => {
@@ -411,9 +765,1332 @@ describe("pont_network", () => {
         const vc2AfterLamports = (await program.provider.connection.getAccountInfo(vc2.publicKey)).lamports;
         const vc3AfterLamports = (await program.provider.connection.getAccountInfo(vc3.publicKey)).lamports;
 
-        expect(vc1AfterLamports).to.equal(Math.floor(1/6 * 10 * 0.01 * LAMPORTS_PER_SOL)  + vc1BeforeLamports);
-        expect(vc2AfterLamports).to.equal(Math.floor(1/3 * 10 * 0.01 * LAMPORTS_PER_SOL) + vc2BeforeLamports);
-        expect(vc3AfterLamports).to.equal(Math.floor(1/2 * 10 * 0.01 * LAMPORTS_PER_SOL) + vc3BeforeLamports);
+        // Ten 0.01 SOL fingerprint fees shared by 600 staked shares
+        const fees = Array(10).fill(new anchor.BN(0.01 * LAMPORTS_PER_SOL));
+        const totalStaked = new anchor.BN(600 * LAMPORTS_PER_SOL);
+        expect(vc1AfterLamports).to.equal(vc1BeforeLamports + rewardsFor(new anchor.BN(100 * LAMPORTS_PER_SOL), fees, totalStaked));
+        expect(vc2AfterLamports).to.equal(vc2BeforeLamports + rewardsFor(new anchor.BN(200 * LAMPORTS_PER_SOL), fees, totalStaked));
+        expect(vc3AfterLamports).to.equal(vc3BeforeLamports + rewardsFor(new anchor.BN(300 * LAMPORTS_PER_SOL), fees, totalStaked));
+        // Every fee is rounded down, leaving a lamport of the 0.05 SOL share behind
+        expect(vc3AfterLamports).to.equal(vc3BeforeLamports + 0.05 * LAMPORTS_PER_SOL - 1);
+    });
+
+    it("Pays a fingerprint fee in a whitelisted SPL mint and claims token rewards", async () => {
+        const feeMint = await createMint(program.provider.connection, shipManagement, shipManagement.publicKey, null, 6);
+        const feeVault = getAssociatedTokenAddressSync(feeMint, fundraisingAccount, true);
+
+        await program.methods.setFeeMint(new anchor.BN(6000), new anchor.BN(0)).accounts({
+            admin: shipManagement.publicKey,
+            feeMint,
+        }).signers([shipManagement]).rpc();
+
+        const shipTokenAccount = await getOrCreateAssociatedTokenAccount(program.provider.connection, ship4, feeMint, ship4.publicKey);
+        await mintTo(program.provider.connection, shipManagement, feeMint, shipTokenAccount.address, shipManagement, 1_000_000);
+
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship4.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 2, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const lamportFeesBefore = (await program.account.fundraisingAccount.fetch(fundraisingAccount)).totalFeesCollected.toNumber();
+
+        await program.methods
+            .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                feePayerTokenAccount: shipTokenAccount.address,
+                feeVault,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .signers([ship4])
+            .rpc();
+
+        const fundraising = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        expect(fundraising.totalFeesCollected.toNumber()).to.equal(lamportFeesBefore);
+        expect(fundraising.tokenFeesCollected[0].mint.toBase58()).to.equal(feeMint.toBase58());
+        expect(fundraising.tokenFeesCollected[0].amount.toNumber()).to.equal(6000);
+        expect(Number((await getAccount(program.provider.connection, feeVault)).amount)).to.equal(6000);
+
+        await program.methods.claimTokenRewards().accounts({
+            user: vc3.publicKey,
+            feeMint,
+        }).signers([vc3]).rpc();
+
+        const vc3TokenAccount = getAssociatedTokenAddressSync(feeMint, vc3.publicKey);
+        expect(Number((await getAccount(program.provider.connection, vc3TokenAccount)).amount)).to.equal(3000);
+
+        // Claiming again pays nothing until new fees come in
+        await program.methods.claimTokenRewards().accounts({
+            user: vc3.publicKey,
+            feeMint,
+        }).signers([vc3]).rpc();
+        expect(Number((await getAccount(program.provider.connection, vc3TokenAccount)).amount)).to.equal(3000);
+
+        // Removing the mint stops new fees in it but keeps the pool claimable
+        await program.methods
+            .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                feePayerTokenAccount: shipTokenAccount.address,
+                feeVault,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .signers([ship4])
+            .rpc();
+
+        await program.methods.removeFeeMint(feeMint).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+        const [removed] = (await program.account.fundraisingAccount.fetch(fundraisingAccount)).tokenFeesCollected;
+        expect(removed.mint.toBase58()).to.equal(feeMint.toBase58());
+        expect(removed.amount.toNumber()).to.equal(12000);
+
+        await program.methods.claimTokenRewards().accounts({
+            user: vc3.publicKey,
+            feeMint,
+        }).signers([vc3]).rpc();
+        expect(Number((await getAccount(program.provider.connection, vc3TokenAccount)).amount)).to.equal(6000);
+
+        try {
+            await program.methods
+                .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+                .accountsPartial({
+                    dataAccount,
+                    ship: ship4.publicKey,
+                    feePayerTokenAccount: shipTokenAccount.address,
+                    feeVault,
+                    tokenProgram: TOKEN_PROGRAM_ID,
+                })
+                .signers([ship4])
+                .rpc();
+            expect.fail("Fees in a removed mint should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("FeeMintNotWhitelisted");
+        }
+
+        // Adding the mint back resumes the same pool
+        await program.methods.setFeeMint(new anchor.BN(6000), new anchor.BN(0)).accounts({
+            admin: shipManagement.publicKey,
+            feeMint,
+        }).signers([shipManagement]).rpc();
+
+        const [readded] = (await program.account.fundraisingAccount.fetch(fundraisingAccount)).tokenFeesCollected;
+        expect(readded.poolId.toNumber()).to.equal(removed.poolId.toNumber());
+        expect(readded.amount.toNumber()).to.equal(12000);
+    });
+
+    it("Sponsors fingerprint fees from the fleet fee escrow", async () => {
+        const [feeEscrow] = PublicKey.findProgramAddressSync(
+            [Buffer.from("fee_escrow"), shipManagement.publicKey.toBuffer()],
+            program.programId
+        );
+
+        await program.methods.initializeFeeEscrow(new anchor.BN(0.5 * LAMPORTS_PER_SOL)).accounts({
+            shipManagement: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        await program.methods.topUpFeeEscrow(new anchor.BN(0.5 * LAMPORTS_PER_SOL)).accounts({
+            shipManagement: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        const [shipAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const { dataAccounts } = await program.account.shipAccount.fetch(shipAccount);
+        const dataAccount = dataAccounts[dataAccounts.length - 2];
+
+        const shipLamportsBefore = await program.provider.connection.getBalance(ship4.publicKey);
+        const escrowLamportsBefore = await program.provider.connection.getBalance(feeEscrow);
+
+        const lowBalance = new Promise<any>((resolve) => {
+            const listener = program.addEventListener("feeEscrowLowBalance", (event) => {
+                program.removeEventListener(listener);
+                resolve(event);
+            });
+        });
+
+        await program.methods
+            .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                feeEscrow,
+                shipAccount,
+            })
+            .signers([ship4])
+            .rpc();
+
+        expect(await program.provider.connection.getBalance(ship4.publicKey)).to.equal(shipLamportsBefore);
+        expect(await program.provider.connection.getBalance(feeEscrow)).to.equal(escrowLamportsBefore - 0.01 * LAMPORTS_PER_SOL);
+        expect((await lowBalance).balance.toNumber()).to.equal(0.49 * LAMPORTS_PER_SOL);
+
+        await program.methods.withdrawFeeEscrow(new anchor.BN(0.49 * LAMPORTS_PER_SOL)).accounts({
+            shipManagement: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+    });
+
+    it("Spends prepaid fingerprint credits before charging fees", async () => {
+        const config = await program.account.protocolConfig.fetch(protocolConfig);
+        await program.methods.updateFeeSchedule({
+            ...config.feeSchedule,
+            batchFee: new anchor.BN(0.002 * LAMPORTS_PER_SOL),
+            creditPrice: new anchor.BN(0.005 * LAMPORTS_PER_SOL),
+        }).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        const [shipAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+
+        await program.methods.purchaseCredits(new anchor.BN(2)).accountsPartial({
+            payer: shipManagement.publicKey,
+            shipAccount,
+        }).signers([shipManagement]).rpc();
+
+        expect((await program.account.shipAccount.fetch(shipAccount)).fingerprintCredits.toNumber()).to.equal(2);
+
+        const { dataAccounts } = await program.account.shipAccount.fetch(shipAccount);
+        const dataAccount = dataAccounts[dataAccounts.length - 2];
+        const shipLamportsBefore = await program.provider.connection.getBalance(ship4.publicKey);
+
+        await program.methods
+            .addMultipleDataFingerprints(
+                [Buffer.from("a"), Buffer.from("b"), Buffer.from("c")],
+                [Buffer.alloc(16), Buffer.alloc(16), Buffer.alloc(16)],
+                [Buffer.alloc(12), Buffer.alloc(12), Buffer.alloc(12)],
+                [new anchor.BN(Date.now()), new anchor.BN(Date.now()), new anchor.BN(Date.now())],
+            )
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                shipAccount,
+            })
+            .signers([ship4])
+            .rpc();
+
+        // Two fingerprints covered by credits, one paid per call plus the batch fee
+        expect((await program.account.shipAccount.fetch(shipAccount)).fingerprintCredits.toNumber()).to.equal(0);
+        expect(await program.provider.connection.getBalance(ship4.publicKey)).to.equal(shipLamportsBefore - 0.012 * LAMPORTS_PER_SOL);
+
+        await program.methods.purchaseCredits(new anchor.BN(2)).accountsPartial({
+            payer: shipManagement.publicKey,
+            shipAccount,
+        }).signers([shipManagement]).rpc();
+        const shipLamportsBeforeCovered = await program.provider.connection.getBalance(ship4.publicKey);
+
+        await program.methods
+            .addMultipleDataFingerprints(
+                [Buffer.from("d"), Buffer.from("e")],
+                [Buffer.alloc(16), Buffer.alloc(16)],
+                [Buffer.alloc(12), Buffer.alloc(12)],
+                [new anchor.BN(Date.now()), new anchor.BN(Date.now())],
+            )
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                shipAccount,
+            })
+            .signers([ship4])
+            .rpc();
+
+        // Credits never cover the batch fee, even when they cover every fingerprint
+        expect((await program.account.shipAccount.fetch(shipAccount)).fingerprintCredits.toNumber()).to.equal(0);
+        expect(await program.provider.connection.getBalance(ship4.publicKey)).to.equal(shipLamportsBeforeCovered - 0.002 * LAMPORTS_PER_SOL);
+
+        await program.methods.updateFeeSchedule({
+            ...config.feeSchedule,
+            creditPrice: new anchor.BN(0.005 * LAMPORTS_PER_SOL),
+        }).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+    });
+
+    it("Charges the batch fee and volume discount on multi-fingerprint batches", async () => {
+        const config = await program.account.protocolConfig.fetch(protocolConfig);
+        await program.methods.updateFeeSchedule({
+            ...config.feeSchedule,
+            batchFee: new anchor.BN(0.002 * LAMPORTS_PER_SOL),
+            volumeDiscountThreshold: 4,
+            volumeDiscountBps: 2500,
+        }).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        const [shipAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const { dataAccounts } = await program.account.shipAccount.fetch(shipAccount);
+        const dataAccount = dataAccounts[dataAccounts.length - 2];
+        const timestamps = [0, 1, 2, 3].map((i) => new anchor.BN(Date.now() + i));
+
+        try {
+            await program.methods
+                .addMultipleDataFingerprints(
+                    [Buffer.from("a"), Buffer.from("b"), Buffer.from("c"), Buffer.from("d")],
+                    [Buffer.alloc(16), Buffer.alloc(16), Buffer.alloc(16)],
+                    [Buffer.alloc(12), Buffer.alloc(12), Buffer.alloc(12), Buffer.alloc(12)],
+                    timestamps,
+                )
+                .accountsPartial({
+                    dataAccount,
+                    ship: ship4.publicKey,
+                    shipAccount,
+                })
+                .signers([ship4])
+                .rpc();
+            expect.fail("Expected batch with mismatched lengths to be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("BatchLengthMismatch");
+        }
+
+        const shipLamportsBefore = await program.provider.connection.getBalance(ship4.publicKey);
+        const { totalFeesCollected } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+
+        let listener: number;
+        const batchFeeCollected = new Promise<any>((resolve) => {
+            listener = program.addEventListener("batchFeeCollected", (event) => resolve(event));
+        });
+
+        await program.methods
+            .addMultipleDataFingerprints(
+                [Buffer.from("a"), Buffer.from("b"), Buffer.from("c"), Buffer.from("d")],
+                [Buffer.alloc(16), Buffer.alloc(16), Buffer.alloc(16), Buffer.alloc(16)],
+                [Buffer.alloc(12), Buffer.alloc(12), Buffer.alloc(12), Buffer.alloc(12)],
+                timestamps,
+            )
+            .accountsPartial({
+                dataAccount,
+                ship: ship4.publicKey,
+                shipAccount,
+            })
+            .signers([ship4])
+            .rpc();
+
+        const event = await batchFeeCollected;
+        await program.removeEventListener(listener);
+
+        // 4 * 0.01 SOL with a 25% discount, plus the 0.002 SOL batch fee
+        const expectedFee = 0.032 * LAMPORTS_PER_SOL;
+        expect(await program.provider.connection.getBalance(ship4.publicKey)).to.equal(shipLamportsBefore - expectedFee);
+        expect(event.fingerprintCount.toNumber()).to.equal(4);
+        expect(event.feeMint).to.be.null;
+        expect(event.fee.toNumber()).to.equal(expectedFee);
+        expect(event.totalFeesCollected.toNumber()).to.equal(totalFeesCollected.toNumber() + expectedFee);
+
+        await program.methods.updateFeeSchedule(config.feeSchedule).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+    });
+
+    it("Withdraws unstaked shares only after the cooldown", async () => {
+        const UNSTAKE_COOLDOWN = 3; // seconds
+        await program.methods.setUnstakeCooldown(new anchor.BN(UNSTAKE_COOLDOWN)).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        const { tokenMint, totalStaked, rewardPerToken } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const [vc1Stake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), vc1.publicKey.toBuffer()],
+            program.programId
+        );
+        const expectedRewards = pendingRewards(await program.account.userAccount.fetch(vc1Stake), rewardPerToken);
+
+        // Fees collected since the last claim are paid out before the stake shrinks
+        const rewardsClaimed = new Promise<any>((resolve) => {
+            const listener = program.addEventListener("rewardsClaimed", (event) => {
+                program.removeEventListener(listener);
+                resolve(event);
+            });
+        });
+
+        await program.methods.requestUnstake(new anchor.BN(50 * LAMPORTS_PER_SOL)).accounts({
+            user: vc1.publicKey,
+        }).signers([vc1]).rpc();
+
+        expect(expectedRewards).to.be.greaterThan(0);
+        expect((await rewardsClaimed).amount.toNumber()).to.equal(expectedRewards);
+
+        const fundraising = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        expect(fundraising.totalStaked.toNumber()).to.equal(totalStaked.toNumber() - 50 * LAMPORTS_PER_SOL);
+
+        try {
+            await program.methods.withdrawUnstaked().accounts({
+                user: vc1.publicKey,
+            }).signers([vc1]).rpc();
+            expect.fail("withdrew during the cooldown");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("UnstakeCooldownActive");
+        }
+
+        await new Promise((resolve) => setTimeout(resolve, (UNSTAKE_COOLDOWN + 1) * 1000));
+
+        const vc1TokenAccount = getAssociatedTokenAddressSync(tokenMint, vc1.publicKey);
+        const vc1TokensBefore = Number((await getAccount(program.provider.connection, vc1TokenAccount)).amount);
+
+        await program.methods.withdrawUnstaked().accounts({
+            user: vc1.publicKey,
+        }).signers([vc1]).rpc();
+
+        expect(Number((await getAccount(program.provider.connection, vc1TokenAccount)).amount)).to.equal(vc1TokensBefore + 50 * LAMPORTS_PER_SOL);
+    });
+
+    it("Rejects migrating a fundraising account already in the current layout", async () => {
+        try {
+            await program.methods.migrateFundraisingAccount().accounts({
+                admin: shipManagement.publicKey,
+            }).signers([shipManagement]).rpc();
+            expect.fail("migrated a current fundraising account");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("NotLegacyFundraisingAccount");
+        }
+    });
+
+    it("Boosts locked stakes and rejects unstaking before the lock expires", async () => {
+        const LOCK_DURATION = 3600; // seconds
+        await program.methods.setLockTiers([
+            { lockDuration: new anchor.BN(LOCK_DURATION), rewardMultiplierBps: 15000 },
+        ]).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        try {
+            await program.methods.stake(new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(60)).accounts({
+                sender: vc1.publicKey,
+            }).signers([vc1]).rpc();
+            expect.fail("staked with an unknown lock tier");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("LockTierNotFound");
+        }
+
+        const { totalStaked } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        await program.methods.stake(new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(LOCK_DURATION)).accounts({
+            sender: vc1.publicKey,
+        }).signers([vc1]).rpc();
+
+        // The whole position is relocked, so the earlier 50 shares are boosted too
+        const [vc1Stake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), vc1.publicKey.toBuffer()],
+            program.programId
+        );
+        const userStake = await program.account.userAccount.fetch(vc1Stake);
+        expect(userStake.amountStaked.toNumber()).to.equal(60 * LAMPORTS_PER_SOL);
+        expect(userStake.weightedStake.toNumber()).to.equal(90 * LAMPORTS_PER_SOL);
+
+        const fundraising = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        expect(fundraising.totalStaked.toNumber()).to.equal(totalStaked.toNumber() + 40 * LAMPORTS_PER_SOL);
+
+        try {
+            await program.methods.requestUnstake(new anchor.BN(10 * LAMPORTS_PER_SOL)).accounts({
+                user: vc1.publicKey,
+            }).signers([vc1]).rpc();
+            expect.fail("unstaked a locked stake");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("StakeLocked");
+        }
+    });
+
+    it("Pays late stakers only the fees collected after they staked", async () => {
+        const lateStaker = anchor.web3.Keypair.generate();
+        await airdropLamports(lateStaker.publicKey, LAMPORTS_PER_SOL);
+
+        const { tokenMint } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const lateStakerTokenAccount = await getOrCreateAssociatedTokenAccount(program.provider.connection, vc1, tokenMint, lateStaker.publicKey);
+        await transfer(
+            program.provider.connection,
+            vc1,
+            getAssociatedTokenAddressSync(tokenMint, vc1.publicKey),
+            lateStakerTokenAccount.address,
+            vc1,
+            10 * LAMPORTS_PER_SOL,
+        );
+
+        // Plenty of fees were collected before this stake, none of them are owed to it
+        await program.methods.stake(new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(0)).accounts({
+            sender: lateStaker.publicKey,
+        }).signers([lateStaker]).rpc();
+
+        const lamportsAfterStake = await program.provider.connection.getBalance(lateStaker.publicKey);
+        await program.methods.claimRewards().accounts({
+            user: lateStaker.publicKey,
+            tokenMint,
+        }).signers([lateStaker]).rpc();
+        expect(await program.provider.connection.getBalance(lateStaker.publicKey)).to.equal(lamportsAfterStake);
+
+        const [shipAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const { dataAccounts } = await program.account.shipAccount.fetch(shipAccount);
+        const fundraisingBefore = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+
+        await program.methods
+            .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+            .accountsPartial({
+                dataAccount: dataAccounts[dataAccounts.length - 2],
+                ship: ship4.publicKey,
+            })
+            .signers([ship4])
+            .rpc();
+
+        const fee = new anchor.BN(0.01 * LAMPORTS_PER_SOL);
+        const { rewardPerToken } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        expect(rewardPerToken.toString()).to.equal(
+            fundraisingBefore.rewardPerToken.add(fee.mul(REWARD_PRECISION).div(fundraisingBefore.totalStaked)).toString()
+        );
+
+        const [lateStake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), lateStaker.publicKey.toBuffer()],
+            program.programId
+        );
+        const expectedRewards = pendingRewards(await program.account.userAccount.fetch(lateStake), rewardPerToken);
+        expect(expectedRewards).to.be.greaterThan(0);
+
+        await program.methods.claimRewards().accounts({
+            user: lateStaker.publicKey,
+            tokenMint,
+        }).signers([lateStaker]).rpc();
+        expect(await program.provider.connection.getBalance(lateStaker.publicKey)).to.equal(lamportsAfterStake + expectedRewards);
+    });
+
+    it("Keeps accrued token rewards when topping up a stake", async () => {
+        const [{ mint: feeMint }] = (await program.account.fundraisingAccount.fetch(fundraisingAccount)).tokenFeesCollected;
+        const feeVault = getAssociatedTokenAddressSync(feeMint, fundraisingAccount, true);
+        const shipTokenAccount = getAssociatedTokenAddressSync(feeMint, ship4.publicKey);
+
+        const [shipAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const { dataAccounts } = await program.account.shipAccount.fetch(shipAccount);
+
+        await program.methods
+            .addDataFingerprint(Buffer.from("ciphertext"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+            .accountsPartial({
+                dataAccount: dataAccounts[dataAccounts.length - 2],
+                ship: ship4.publicKey,
+                feePayerTokenAccount: shipTokenAccount,
+                feeVault,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .signers([ship4])
+            .rpc();
+
+        const [vc1Stake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), vc1.publicKey.toBuffer()],
+            program.programId
+        );
+        const [tokenFees] = (await program.account.fundraisingAccount.fetch(fundraisingAccount)).tokenFeesCollected;
+        const userStake = await program.account.userAccount.fetch(vc1Stake);
+        const tokenRewards = userStake.tokenRewards.find((rewards) => rewards.poolId.eq(tokenFees.poolId))
+            ?? { rewardDebt: new anchor.BN(0), unclaimedRewards: new anchor.BN(0) };
+        const expectedRewards = pendingRewards(
+            { weightedStake: userStake.weightedStake, ...tokenRewards },
+            tokenFees.rewardPerToken,
+        );
+        expect(expectedRewards).to.be.greaterThan(0);
+
+        // Topping up settles the token rewards instead of resetting them
+        await program.methods.stake(new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(3600)).accounts({
+            sender: vc1.publicKey,
+        }).signers([vc1]).rpc();
+
+        await program.methods.claimTokenRewards().accounts({
+            user: vc1.publicKey,
+            feeMint,
+        }).signers([vc1]).rpc();
+
+        const vc1FeeTokenAccount = getAssociatedTokenAddressSync(feeMint, vc1.publicKey);
+        expect(Number((await getAccount(program.provider.connection, vc1FeeTokenAccount)).amount)).to.equal(expectedRewards);
+    });
+
+    it("Lets anyone end a lock boost once the lock has expired", async () => {
+        const SHORT_LOCK_DURATION = 3; // seconds
+        await program.methods.setLockTiers([
+            { lockDuration: new anchor.BN(3600), rewardMultiplierBps: 15000 },
+            { lockDuration: new anchor.BN(SHORT_LOCK_DURATION), rewardMultiplierBps: 12000 },
+        ]).accounts({
+            admin: shipManagement.publicKey,
+        }).signers([shipManagement]).rpc();
+
+        const staker = anchor.web3.Keypair.generate();
+        await airdropLamports(staker.publicKey, LAMPORTS_PER_SOL);
+
+        const { tokenMint } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const stakerTokenAccount = await getOrCreateAssociatedTokenAccount(program.provider.connection, vc1, tokenMint, staker.publicKey);
+        await transfer(
+            program.provider.connection,
+            vc1,
+            getAssociatedTokenAddressSync(tokenMint, vc1.publicKey),
+            stakerTokenAccount.address,
+            vc1,
+            10 * LAMPORTS_PER_SOL,
+        );
+
+        await program.methods.stake(new anchor.BN(10 * LAMPORTS_PER_SOL), new anchor.BN(SHORT_LOCK_DURATION)).accounts({
+            sender: staker.publicKey,
+        }).signers([staker]).rpc();
+
+        const [userStake] = PublicKey.findProgramAddressSync(
+            [Buffer.from("user_stake"), staker.publicKey.toBuffer()],
+            program.programId
+        );
+        expect((await program.account.userAccount.fetch(userStake)).weightedStake.toNumber()).to.equal(12 * LAMPORTS_PER_SOL);
+
+        try {
+            await program.methods.expireStakeLock().accounts({
+                caller: vc3.publicKey,
+                userStake,
+            }).signers([vc3]).rpc();
+            expect.fail("ended the boost of a locked stake");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("StakeLocked");
+        }
+
+        await new Promise((resolve) => setTimeout(resolve, (SHORT_LOCK_DURATION + 1) * 1000));
+
+        const { totalStaked, rewardPerToken } = await program.account.fundraisingAccount.fetch(fundraisingAccount);
+        const stakeBefore = await program.account.userAccount.fetch(userStake);
+        const stakerLamports = await program.provider.connection.getBalance(staker.publicKey);
+
+        // The staker does not need to sign, anyone can stop the boosted accrual
+        await program.methods.expireStakeLock().accounts({
+            caller: vc3.publicKey,
+            userStake,
+        }).signers([vc3]).rpc();
+
+        const stakeAfter = await program.account.userAccount.fetch(userStake);
+        expect(stakeAfter.weightedStake.toNumber()).to.equal(10 * LAMPORTS_PER_SOL);
+        expect(stakeAfter.rewardMultiplierBps).to.equal(10000);
+        expect(stakeAfter.unclaimedRewards.toNumber()).to.equal(pendingRewards(stakeBefore, rewardPerToken));
+        expect((await program.account.fundraisingAccount.fetch(fundraisingAccount)).totalStaked.toNumber()).to.equal(totalStaked.toNumber() - 2 * LAMPORTS_PER_SOL);
+        expect(await program.provider.connection.getBalance(staker.publicKey)).to.equal(stakerLamports);
+
+        try {
+            await program.methods.expireStakeLock().accounts({
+                caller: vc3.publicKey,
+                userStake,
+            }).signers([vc3]).rpc();
+            expect.fail("ended a boost twice");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("StakeNotBoosted");
+        }
+    });
+
+    it("Finalizes the first Data Account", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship4.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 2, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        await program.methods
+            .finalizeDataAccount()
+            .accountsPartial({
+                dataAccount,
+                authority: shipManagement.publicKey,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        const account = await program.account.dataAccount.fetch(dataAccount);
+        expect(account.isFinalized).to.equal(true);
+        expect(account.endTimestamp.gte(shipAccount.dataAccountStartingTimestamps[shipAccount.dataAccounts.length - 2])).to.equal(true);
+
+        try {
+            await program.methods
+                .addDataFingerprint(Buffer.from("sealed"), Buffer.alloc(16), Buffer.alloc(12), new anchor.BN(Date.now()))
+                .accountsPartial({
+                    dataAccount,
+                    ship: ship4.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .signers([ship4])
+                .rpc();
+            expect.fail("Finalized data account should reject new fingerprints");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("DataAccountFinalized");
+        }
+    });
+
+    it("Archives the finalized Data Account", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship4.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccountBefore = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship4.publicKey.toBuffer(), new anchor.BN(shipAccountBefore.dataAccounts.length - 2, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+        const finalizedAccount = await program.account.dataAccount.fetch(dataAccount);
+
+        // A pending request keeps the sailing from being archived until it is answered
+        const inspector = anchor.web3.Keypair.generate();
+        const inspector_x25519pk = x25519.getPublicKey(inspector.secretKey.slice(0, 32));
+        await airdropLamports(inspector.publicKey, LAMPORTS_PER_SOL);
+
+        const [externalObserversAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+        await program.methods
+            .externalObserverRequest(new PublicKey(inspector_x25519pk), "Port State Control", "Inspection", null, null, new anchor.BN(0))
+            .accountsStrict({
+                dataAccount,
+                externalObserversAccount,
+                depositAccount: null,
+                externalObserver: inspector.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([inspector])
+            .rpc();
+
+        try {
+            await program.methods
+                .archiveDataAccount()
+                .accountsPartial({
+                    shipAccount: shipAccountAddress,
+                    dataAccount,
+                    shipManagement: shipManagement.publicKey,
+                })
+                .signers([shipManagement])
+                .rpc();
+            expect.fail("archived a sailing with a pending observer request");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("PendingObserverRequests");
+        }
+
+        await program.methods
+            .rejectExternalObserverRequest(inspector.publicKey)
+            .accountsStrict({
+                externalObserversAccount,
+                dataAccount,
+                shipAccount: shipAccountAddress,
+                shipManagement: shipManagement.publicKey,
+                externalObserver: inspector.publicKey,
+                depositAccount: null,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        await program.methods
+            .archiveDataAccount()
+            .accountsPartial({
+                shipAccount: shipAccountAddress,
+                dataAccount,
+                shipManagement: shipManagement.publicKey,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+        expect(shipAccount.archivedSailings.length).to.equal(1);
+        expect(shipAccount.archivedSailings[0].dataAccount.toBase58()).to.equal(dataAccount.toBase58());
+        expect(shipAccount.archivedSailings[0].fingerprintCount.toNumber()).to.equal(finalizedAccount.fingerprintTree.nextIndex.toNumber());
+        expect(shipAccount.dataAccounts.length).to.equal(shipAccountBefore.dataAccounts.length);
+
+        expect(await program.account.dataAccount.fetchNullable(dataAccount)).to.be.null;
+    });
+
+    // Fingerprints committed to ship1's data account, in insertion order
+    const committedFingerprints: Buffer[] = [];
+
+    it("Requests to be an External Observer", async () => {
+        const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount, bump3] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const tx = await program.methods
+            .externalObserverRequest(new PublicKey(eo3_x25519pk), "Port of Rotterdam", "Port state control inspection", null, null, new anchor.BN(0))
+            .accountsStrict({
+                dataAccount,
+                externalObserversAccount,
+                depositAccount: null,
+                externalObserver: eo3.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([eo3])
+            .rpc();
+
+        console.log("External Observer requested with transaction signature", tx);
+
+        const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+
+        // Convert PublicKey objects to strings for comparison
+        const unapprovedExternalObservers = account.unapprovedExternalObservers.map((pk: PublicKey) => pk.toString());
+        const externalObserverPublicKey = eo3.publicKey.toString();
+
+        expect(unapprovedExternalObservers).to.include(externalObserverPublicKey);
+    });
+
+    it("Approves an External Observer", async () => {
+        const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount, bump3] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const accountPreTx = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+        const externalObserverIndex = accountPreTx.unapprovedExternalObservers.findIndex((pk: PublicKey) => pk.equals(eo3.publicKey));
+        const eo_x25519_pk = accountPreTx.unapprovedExternalObserversX25519Pks[externalObserverIndex];
+
+        const encryptedExternalObserverKey = await ecies25519.encrypt(keyBytes, eo_x25519_pk.toBytes())
+
+        try {
+            await program.methods
+                .addExternalObserver(eo1.publicKey, Array.from(encryptedExternalObserverKey), { reader: {} }, null, null)
+                .accountsStrict({
+                    externalObserversAccount,
+                    dataAccount,
+                    shipAccount: shipAccountAddress,
+                    shipManagement: shipManagement.publicKey,
+                    externalObserver: eo1.publicKey,
+                    depositAccount: null,
+                    systemProgram: SystemProgram.programId,
+                    fundraisingAccount,
+                    protocolConfig,
+                })
+                .signers([shipManagement])
+                .rpc();
+            expect.fail("Approving an observer without a request should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("ExternalObserverRequestNotFound");
+        }
+
+        // Approve the external observer
+        const tx = await program.methods
+            .addExternalObserver(eo3.publicKey, Array.from(encryptedExternalObserverKey), { reader: {} }, null, null)
+            .accountsStrict({
+                externalObserversAccount,
+                dataAccount,
+                shipAccount: shipAccountAddress,
+                shipManagement: shipManagement.publicKey,
+                externalObserver: eo3.publicKey,
+                depositAccount: null,
+                systemProgram: SystemProgram.programId,
+                fundraisingAccount,
+                protocolConfig,
+            }).
+            signers([shipManagement])
+            .rpc();
+
+        console.log("External Observer approved with transaction signature", tx);
+
+        const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+
+        // Convert PublicKey objects to strings for comparison
+        const unapprovedExternalObservers = account.unapprovedExternalObservers.map((pk: PublicKey) => pk.toString());
+        const externalObservers = account.externalObservers.map((pk: PublicKey) => pk.toString());
+        const externalObserverPublicKey = eo3.publicKey.toString();
+        const externalObserversEncryptedKeys = account.externalObserversMasterKeys.map((key: number[]) => Uint8Array.from(key));
+
+        expect(unapprovedExternalObservers).to.not.include(externalObserverPublicKey);
+        expect(externalObservers).to.include(externalObserverPublicKey);
+
+        // Decrypt the external observer key
+        const decryptedExternalObserverKey = await ecies25519.decrypt(externalObserversEncryptedKeys[0], eo3.secretKey.slice(0, 32));
+        const decryptedExternalObserverKeyBuffer = Buffer.from(decryptedExternalObserverKey);
+        expect(decryptedExternalObserverKeyBuffer).to.deep.equal(keyBytes);
+    });
+
+    it("Checks an External Observer role and access window", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        // eo3 was approved as a reader without bounds
+        await program.methods
+            .checkObserverAccess(eo3.publicKey, { reader: {} })
+            .accountsPartial({ dataAccount })
+            .rpc();
+
+        await program.methods
+            .checkObserverAccess(eo3.publicKey, { verifier: {} })
+            .accountsPartial({ dataAccount })
+            .rpc();
+
+        try {
+            await program.methods
+                .requestDataExport(new anchor.BN(0), new anchor.BN(Date.now()))
+                .accountsPartial({ dataAccount, externalObserver: eo3.publicKey })
+                .signers([eo3])
+                .rpc();
+            expect.fail("Readers should not be able to request exports");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("InsufficientObserverRole");
+        }
+
+        try {
+            await program.methods
+                .checkObserverAccess(eo1.publicKey, { verifier: {} })
+                .accountsPartial({ dataAccount })
+                .rpc();
+            expect.fail("Unknown observer should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("ExternalObserverNotFound");
+        }
+    });
+
+    const sensorData = {
+        "lat": -4.96579,
+        "long": -1.72182,
+        "mileage": 0.25,
+        "engineLoad": 79.81,
+        "fuelLevel": 99.89,
+        "seaState": "high",
+        "seaSurfaceTemperature": 11.7,
+        "airTemp": 25.6,
+        "humidity": 58.22,
+        "barometricPressure": 999.71,
+        "cargoStatus": "INTRANSIT",
+        "time": 1725629220025
+    };
+    const sensorDataJson = JSON.stringify(sensorData);
+    const sensorDataBuffer = Buffer.from(sensorDataJson);
+
+    it("Adds a Data Fingerprint", async () => {
+        const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const iv = new Uint32Array(3);
+        crypto.getRandomValues(iv);
+
+        const encryptedData = encrypt(sensorDataBuffer, masterKey, iv);
+        const ciphertext = encryptedData.ciphertext;
+        const tag = encryptedData.tag;
+        console.log("Encrypted Data: ", encryptedData);
+
+        const serializedEncryptedData = serializeEncryptedData(encryptedData);
+        const ciphertextBuffer = serializedEncryptedData.ciphertext;
+        const tagBuffer = serializedEncryptedData.tag;
+        const ivBuffer = serializedEncryptedData.iv;
+        console.log("\nSerialized Encrypted Data: ", serializedEncryptedData);
+
+        const dataFingerprint = await blake3(sensorDataBuffer);
+        const encryptedDataFingerprint = await blake3(ciphertextBuffer);
+        const dataTimestamp = Date.now();
+
+        const tx = await program.methods
+            .addDataFingerprint(ciphertextBuffer, tagBuffer, ivBuffer, new anchor.BN(dataTimestamp))
+            .accountsPartial({
+                dataAccount,
+                ship: ship1.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([ship1])
+            .rpc();
+
+        console.log("Data Fingerprint added with transaction signature", tx);
+
+        const txDetails = await program.provider.connection.getTransaction(tx, {
+            maxSupportedTransactionVersion: 0,
+            commitment: "confirmed",
+        });
+
+        console.log("Transaction Details: ", txDetails);
+
+        committedFingerprints.push(Buffer.from(encryptedDataFingerprint, 'hex'));
+
+        const account = await program.account.dataAccount.fetch(dataAccount);
+        expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(1);
+
+        const rootHex = currentRoot(account.fingerprintTree).toString('hex');
+        console.log("Fingerprint tree root: ", rootHex);
+
+        expect(rootHex).to.equal((await merkleRoot(committedFingerprints)).toString('hex'));
+    });
+
+    it("Adds Multiple Data Fingerprints", async () => {
+        const [shipAccountAddress, bump1] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount, bump2] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const data = [sensorDataBuffer, sensorDataBuffer, sensorDataBuffer];
+
+        const ivs = [new Uint32Array(3), new Uint32Array(3), new Uint32Array(3)];
+        crypto.getRandomValues(ivs[0]);
+        crypto.getRandomValues(ivs[1]);
+        crypto.getRandomValues(ivs[2]);
+
+        const encryptedData = [encrypt(data[0], masterKey, ivs[0]), encrypt(data[1], masterKey, ivs[1]), encrypt(data[2], masterKey, ivs[2])];
+        const ciphertexts = [encryptedData[0].ciphertext, encryptedData[1].ciphertext, encryptedData[2].ciphertext];
+        const tags = [encryptedData[0].tag, encryptedData[1].tag, encryptedData[2].tag];
+        console.log("Encrypted Data: ", encryptedData);
+
+        const serializedEncryptedData = [serializeEncryptedData(encryptedData[0]), serializeEncryptedData(encryptedData[1]), serializeEncryptedData(encryptedData[2])];
+        const ciphertextBuffers = [serializedEncryptedData[0].ciphertext, serializedEncryptedData[1].ciphertext, serializedEncryptedData[2].ciphertext];
+        const tagBuffers = [serializedEncryptedData[0].tag, serializedEncryptedData[1].tag, serializedEncryptedData[2].tag];
+        const ivBuffers = [serializedEncryptedData[0].iv, serializedEncryptedData[1].iv, serializedEncryptedData[2].iv];
+        console.log("\nSerialized Encrypted Data: ", serializedEncryptedData);
+
+        // const dataFingerprints = await blake3(data);
+        const encryptedDataFingerprint = [await blake3(ciphertextBuffers[0]), await blake3(ciphertextBuffers[1]), await blake3(ciphertextBuffers[2])];
+
+        const dataTimestamps = [Date.now(), Date.now() + 1000, Date.now() + 2000];
+
+        const tx = await program.methods
+            .addMultipleDataFingerprints(ciphertextBuffers, tagBuffers, ivBuffers, dataTimestamps.map(ts => new anchor.BN(ts)))
+            .accountsPartial({
+                dataAccount,
+                ship: ship1.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([ship1])
+            .rpc();
+
+        console.log("Data Fingerprint added with transaction signature", tx);
+
+        committedFingerprints.push(...encryptedDataFingerprint.map(fingerprint => Buffer.from(fingerprint, 'hex')));
+
+        const account = await program.account.dataAccount.fetch(dataAccount);
+        expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(4);
+        expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(committedFingerprints)).toString('hex'));
+    });
+
+    it("Verifies a Data Fingerprint inclusion proof", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const leafIndex = 2;
+        const proof = await merkleProof(committedFingerprints, leafIndex);
+
+        await program.methods
+            .verifyFingerprint(Array.from(committedFingerprints[leafIndex]), new anchor.BN(leafIndex), proof.map(node => Array.from(node)))
+            .accountsStrict({ dataAccount })
+            .rpc();
+
+        try {
+            await program.methods
+                .verifyFingerprint(Array.from(committedFingerprints[leafIndex]), new anchor.BN(leafIndex + 1), proof.map(node => Array.from(node)))
+                .accountsStrict({ dataAccount })
+                .rpc();
+            expect.fail("Proof for the wrong position should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("InvalidFingerprintProof");
+        }
+    });
+
+    it("Removes an External Observer", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const tx = await program.methods
+            .removeExternalObserver(eo3.publicKey)
+            .accountsStrict({
+                externalObserversAccount,
+                dataAccount,
+                shipAccount: shipAccountAddress,
+                shipManagement: shipManagement.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        console.log("External Observer removed with transaction signature", tx);
+
+        const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+        const externalObservers = account.externalObservers.map((pk: PublicKey) => pk.toString());
+
+        expect(externalObservers).to.not.include(eo3.publicKey.toString());
+        expect(account.externalObserversMasterKeys.length).to.equal(account.externalObservers.length);
+        expect(account.externalObserversX25519Pks.length).to.equal(account.externalObservers.length);
+    });
+
+    it("Rotates the master key for the remaining External Observers", async () => {
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const newMasterKey = new Uint8Array(32);
+        crypto.getRandomValues(newMasterKey);
+
+        const observersAccount = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+        const newEncryptedKeys = await Promise.all(
+            observersAccount.externalObserversX25519Pks.map(async (pk: PublicKey) => Array.from(await ecies25519.encrypt(newMasterKey, pk.toBytes())))
+        );
+
+        const dataAccountBefore = await program.account.dataAccount.fetch(dataAccount);
+
+        await program.methods
+            .rotateMasterKey(dataAccountBefore.keyEpoch + 1, newEncryptedKeys)
+            .accountsStrict({
+                dataAccount,
+                externalObserversAccount,
+                shipAccount: shipAccountAddress,
+                shipManagement: shipManagement.publicKey,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        const dataAccountAfter = await program.account.dataAccount.fetch(dataAccount);
+        expect(dataAccountAfter.keyEpoch).to.equal(dataAccountBefore.keyEpoch + 1);
+
+        try {
+            await program.methods
+                .rotateMasterKey(dataAccountAfter.keyEpoch, newEncryptedKeys)
+                .accountsStrict({
+                    dataAccount,
+                    externalObserversAccount,
+                    shipAccount: shipAccountAddress,
+                    shipManagement: shipManagement.publicKey,
+                })
+                .signers([shipManagement])
+                .rpc();
+            expect.fail("Reusing a key epoch should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("InvalidKeyEpoch");
+        }
+    });
+
+    it("Rejects an External Observer request", async () => {
+        const eo4 = anchor.web3.Keypair.generate();
+        const eo4_x25519pk = x25519.getPublicKey(eo4.secretKey.slice(0, 32));
+        await airdropLamports(eo4.publicKey, LAMPORTS_PER_SOL);
+
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const [depositAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("observer_request_deposit"), externalObserversAccount.toBuffer(), eo4.publicKey.toBuffer()],
+            program.programId
+        );
+
+        const lamportsBeforeRequest = await program.provider.connection.getBalance(eo4.publicKey);
+
+        await program.methods
+            .externalObserverRequest(new PublicKey(eo4_x25519pk), "Lloyd's Register", "Hull insurance claim", null, null, new anchor.BN(0.1 * LAMPORTS_PER_SOL))
+            .accountsStrict({
+                dataAccount,
+                externalObserversAccount,
+                depositAccount,
+                externalObserver: eo4.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([eo4])
+            .rpc();
+
+        const pendingAccount = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+        const pendingIndex = pendingAccount.unapprovedExternalObservers.findIndex((pk: PublicKey) => pk.equals(eo4.publicKey));
+        expect(pendingAccount.unapprovedExternalObserversApplications[pendingIndex].organisation).to.equal("Lloyd's Register");
+        expect(pendingAccount.unapprovedExternalObserversApplications[pendingIndex].deposit.toNumber()).to.equal(0.1 * LAMPORTS_PER_SOL);
+
+        const lamportsAfterRequest = await program.provider.connection.getBalance(eo4.publicKey);
+
+        const tx = await program.methods
+            .rejectExternalObserverRequest(eo4.publicKey)
+            .accountsStrict({
+                externalObserversAccount,
+                dataAccount,
+                shipAccount: shipAccountAddress,
+                shipManagement: shipManagement.publicKey,
+                externalObserver: eo4.publicKey,
+                depositAccount,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([shipManagement])
+            .rpc();
+
+        console.log("External Observer request rejected with transaction signature", tx);
+
+        const account = await program.account.externalObserversAccount.fetch(externalObserversAccount);
+        const unapprovedExternalObservers = account.unapprovedExternalObservers.map((pk: PublicKey) => pk.toString());
+        expect(unapprovedExternalObservers).to.not.include(eo4.publicKey.toString());
+
+        // Only the transaction fee of the request is lost, the deposit and all rent come back
+        const lamportsAfterRejection = await program.provider.connection.getBalance(eo4.publicKey);
+        expect(lamportsAfterRejection).to.be.greaterThan(lamportsAfterRequest);
+        expect(lamportsBeforeRequest - lamportsAfterRejection).to.equal(5000);
+    });
+
+    it("Only expires External Observer requests after the expiry period", async () => {
+        const eo5 = anchor.web3.Keypair.generate();
+        const eo5_x25519pk = x25519.getPublicKey(eo5.secretKey.slice(0, 32));
+        await airdropLamports(eo5.publicKey, LAMPORTS_PER_SOL);
+
+        const [shipAccountAddress] = PublicKey.findProgramAddressSync(
+            [Buffer.from("ship_account"), ship1.publicKey.toBuffer()],
+            program.programId
+        );
+        const shipAccount = await program.account.shipAccount.fetch(shipAccountAddress);
+
+        const [dataAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("data_account"), ship1.publicKey.toBuffer(), new anchor.BN(shipAccount.dataAccounts.length - 1, "le").toArrayLike(Buffer, "le", 8)],
+            program.programId
+        );
+
+        const [externalObserversAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("external_observers_account"), dataAccount.toBuffer()],
+            program.programId
+        );
+
+        const [depositAccount] = PublicKey.findProgramAddressSync(
+            [Buffer.from("observer_request_deposit"), externalObserversAccount.toBuffer(), eo5.publicKey.toBuffer()],
+            program.programId
+        );
+
+        // A deposit account without a deposit would only lock up rent
+        try {
+            await program.methods
+                .externalObserverRequest(new PublicKey(eo5_x25519pk), "Lloyd's Register", "Hull insurance claim", null, null, new anchor.BN(0))
+                .accountsStrict({
+                    dataAccount,
+                    externalObserversAccount,
+                    depositAccount,
+                    externalObserver: eo5.publicKey,
+                    systemProgram: SystemProgram.programId,
+                })
+                .signers([eo5])
+                .rpc();
+            expect.fail("Deposit account without a deposit should be rejected");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("UnexpectedDepositAccount");
+        }
+        expect(await program.provider.connection.getAccountInfo(depositAccount)).to.be.null;
+
+        await program.methods
+            .externalObserverRequest(new PublicKey(eo5_x25519pk), "Lloyd's Register", "Hull insurance claim", null, null, new anchor.BN(0.1 * LAMPORTS_PER_SOL))
+            .accountsStrict({
+                dataAccount,
+                externalObserversAccount,
+                depositAccount,
+                externalObserver: eo5.publicKey,
+                systemProgram: SystemProgram.programId,
+            })
+            .signers([eo5])
+            .rpc();
+
+        try {
+            await program.methods
+                .expireExternalObserverRequest(eo5.publicKey)
+                .accountsStrict({
+                    dataAccount,
+                    externalObserversAccount,
+                    shipAccount: shipAccountAddress,
+                    shipManagement: shipManagement.publicKey,
+                    externalObserver: eo5.publicKey,
+                    depositAccount,
+                    systemProgram: SystemProgram.programId,
+                })
+                .signers([shipManagement])
+                .rpc();
+            expect.fail("A fresh request should not be expirable");
+        } catch (error) {
+            expect(error.error.errorCode.code).to.equal("ExternalObserverRequestNotExpired");
+        }
+
+        // The deposit stays with the request until it is answered
+        const deposit = await program.account.observerRequestDeposit.fetch(depositAccount);
+        expect(deposit.amount.toNumber()).to.equal(0.1 * LAMPORTS_PER_SOL);
     });
 
 });
@@ -8,6 +8,7 @@ import crypto from 'crypto';
 import * as ecies25519 from 'ecies-25519';
 import * as encUtils from 'enc-utils';
 import { x25519 } from '@noble/curves/ed25519'
+import { currentRoot, merkleRoot } from './merkle'
 
 describe("pont_network", () => {
 	const ship1 = anchor.web3.Keypair.generate();
@@ -20,6 +21,9 @@ describe("pont_network", () => {
 	console.log("Provider: ", anchor.getProvider());
 	// cosnt program = new Program
 	const program = anchor.workspace.PontNetwork as Program<PontNetwork>;
+
+	const [fundraisingAccount] = PublicKey.findProgramAddressSync([Buffer.from("fundraising")], program.programId);
+	const [protocolConfig] = PublicKey.findProgramAddressSync([Buffer.from("protocol_config")], program.programId);
 	
 	const ship2 = anchor.web3.Keypair.generate();
 	const ship3 = anchor.web3.Keypair.generate();
@@ -159,6 +163,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount1,
                 externalObserversAccount: externalObserversAccount1,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship1])
             .rpc();
@@ -189,6 +195,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount2,
                 externalObserversAccount: externalObserversAccount2,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship2])
             .rpc();
@@ -219,6 +227,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount3,
                 externalObserversAccount: externalObserversAccount3,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship3])
             .rpc();
@@ -249,6 +259,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount4,
                 externalObserversAccount: externalObserversAccount4,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship4])
             .rpc();
@@ -274,6 +286,8 @@ describe("pont_network", () => {
                 systemProgram: SystemProgram.programId,
                 dataAccount: dataAccount4_2,
                 externalObserversAccount: externalObserversAccount4_2,
+                fundraisingAccount,
+                protocolConfig,
             })
             .signers([ship4])
             .rpc();
@@ -308,6 +322,7 @@ describe("pont_network", () => {
 			program.programId
 		);
 	
+		const leaves: Buffer[] = [];
 		for (let i = 0; i < 10; i++) {
 			const ivUint32Array = new Uint32Array(3);
 			crypto.getRandomValues(ivUint32Array);
@@ -327,6 +342,13 @@ describe("pont_network", () => {
 				.accountsStrict({
 					dataAccount,
 					ship: ship4.publicKey,
+					fundraisingAccount,
+					protocolConfig,
+					feePayerTokenAccount: null,
+					feeVault: null,
+					tokenProgram: null,
+					feeEscrow: null,
+					shipAccount: shipAccountAddress,
 				})
 				.signers([ship4])
 				.rpc();
@@ -339,10 +361,12 @@ describe("pont_network", () => {
 			});
 	
 			console.log(`Transaction ${i + 1} Details: `, txDetails);
+			leaves.push(Buffer.from(encryptedDataFingerprint, 'hex'));
 		}
 	
 		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(10);
+		expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(10);
+		expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(leaves)).toString('hex'));
 	
 		// for (let i = 0; i < 10; i++) {
 		// 	// Convert byte array (number[]) to Buffer
@@ -371,6 +395,7 @@ describe("pont_network", () => {
 		);
 
         let counter = 1;
+		const leaves: Buffer[] = [];
 		console.log("Counter: ", counter);
         const intervalId = setInterval(async () => {
             if (counter >= 200) {
@@ -395,10 +420,18 @@ describe("pont_network", () => {
 					.accountsStrict({
 						dataAccount,
 						ship: ship4.publicKey,
+						fundraisingAccount,
+						protocolConfig,
+						feePayerTokenAccount: null,
+						feeVault: null,
+						tokenProgram: null,
+						feeEscrow: null,
+						shipAccount: shipAccountAddress,
 					})
 					.signers([ship4])
 					.rpc();
 				console.log("Data Fingerprint added with transaction signature", tx);
+				leaves.push(Buffer.from(encryptedDataFingerprint, 'hex'));
 	
 				const txDetails = await program.provider.connection.getTransaction(tx, {
 					maxSupportedTransactionVersion: 0,
@@ -408,16 +441,8 @@ describe("pont_network", () => {
 				console.log("Transaction Details: ", txDetails);
 	
 				const account = await program.account.dataAccount.fetch(dataAccount);
-				expect(account.fingerprints.length).to.equal(counter + 1);
-	
-				// Convert byte array (number[]) to Buffer
-				const fingerprintBuffer = Buffer.from(account.fingerprints[counter][0]);
-	
-				// Convert Buffer to hex string
-				const fingerprintHex = fingerprintBuffer.toString('hex');
-				console.log("Data Fingerprint: ", fingerprintHex);
-	
-				expect(fingerprintHex).to.equal(encryptedDataFingerprint);
+				expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(counter + 1);
+				expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(leaves)).toString('hex'));
 	
 				console.log("Counter: ", counter);
 				counter++;
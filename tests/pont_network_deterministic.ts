@@ -8,6 +8,7 @@ import crypto from 'crypto';
 import * as ecies25519 from 'ecies-25519';
 import * as encUtils from 'enc-utils';
 import { x25519 } from '@noble/curves/ed25519'
+import { currentRoot, merkleRoot } from './merkle'
 
 describe("pont_network_deterministic", () => {
     // Configure the client to use the local cluster.
@@ -15,6 +16,9 @@ describe("pont_network_deterministic", () => {
     const provider = anchor.AnchorProvider.env();
     const program = anchor.workspace.PontNetwork as Program<PontNetwork>;
 
+    const [fundraisingAccount] = PublicKey.findProgramAddressSync([Buffer.from("fundraising")], program.programId);
+    const [protocolConfig] = PublicKey.findProgramAddressSync([Buffer.from("protocol_config")], program.programId);
+
     // Use fixed keypairs for deterministic tests
     const ship = anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(1));
     const shipManagement = anchor.web3.Keypair.fromSeed(new Uint8Array(32).fill(2));
@@ -27,6 +31,9 @@ describe("pont_network_deterministic", () => {
     const masterKey = new Uint8Array(32).fill(5);
     const keyBytes = new Uint8Array(masterKey.buffer);
 
+    // Fingerprints added by "Adds a Data Fingerprint deterministically", in order
+    let deterministicLeaves: Buffer[] = [];
+
     const eo1_x25519pk = x25519.getPublicKey(eo1.secretKey.slice(0, 32));
     const eo2_x25519pk = x25519.getPublicKey(eo2.secretKey.slice(0, 32));
 
@@ -101,6 +108,8 @@ describe("pont_network_deterministic", () => {
 				systemProgram: SystemProgram.programId,
 				dataAccount,
 				externalObserversAccount,
+				fundraisingAccount,
+				protocolConfig,
 			})
 			.signers([ship])
 			.rpc();
@@ -144,10 +153,11 @@ describe("pont_network_deterministic", () => {
 		})
 
 		const tx = await program.methods
-			.externalObserverRequest(new PublicKey(eo3_x25519pk))
+			.externalObserverRequest(new PublicKey(eo3_x25519pk), "Port of Rotterdam", "Port state control inspection", null, null, new anchor.BN(0))
 			.accountsStrict({
 				dataAccount,
 				externalObserversAccount,
+				depositAccount: null,
 				externalObserver: eo3.publicKey,
 				systemProgram: SystemProgram.programId,
 			})
@@ -214,13 +224,17 @@ describe("pont_network_deterministic", () => {
 
 		// Approve the external observer
 		const tx = await program.methods
-			.addExternalObserver(eo3.publicKey,  Array.from(encryptedExternalObserverKey))
+			.addExternalObserver(eo3.publicKey, Array.from(encryptedExternalObserverKey), { reader: {} }, null, null)
 			.accountsStrict({
 				externalObserversAccount,
 				dataAccount,
 				shipAccount: shipAccountAddress,
 				shipManagement: shipManagement.publicKey,
+				externalObserver: eo3.publicKey,
+				depositAccount: null,
 				systemProgram: SystemProgram.programId,
+				fundraisingAccount,
+				protocolConfig,
 			}).
 			signers([shipManagement])
 			.rpc();
@@ -272,11 +286,20 @@ describe("pont_network_deterministic", () => {
 				.accountsStrict({
 					dataAccount,
 					ship: ship.publicKey,
+					fundraisingAccount,
+					protocolConfig,
+					feePayerTokenAccount: null,
+					feeVault: null,
+					tokenProgram: null,
+					feeEscrow: null,
+					shipAccount: shipAccountAddress,
 				})
 				.signers([ship])
 				.rpc();
 	
 			console.log("Data Fingerprint added with transaction signature", tx);
+
+			return Buffer.from(await blake3(ciphertextBuffer), 'hex');
 		};
 	
 
@@ -292,19 +315,15 @@ describe("pont_network_deterministic", () => {
 			new Uint32Array(3).fill(107)
 		];
 	
+		const leaves: Buffer[] = [];
 		for (let i = 0; i < 8; i++) {
-			await addFingerprint(data, ivs[i]);
+			leaves.push(await addFingerprint(data, ivs[i]));
 			await new Promise(resolve => setTimeout(resolve, 2000)); // Wait for 2 seconds
 		}
 	
 		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(8);
-	
-		for (let i = 0; i < 8; i++) {
-			const fingerprintBuffer = Buffer.from(account.fingerprints[i][0]);
-			const fingerprintHex = fingerprintBuffer.toString('hex');
-			console.log(`Data Fingerprint ${i + 1}: `, fingerprintHex);
-		}
+		expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(8);
+		expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(leaves)).toString('hex'));
 	});
 
 	it("Adds Fingerprint data in batches every 2 seconds for 7.5 seconds", async () => {
@@ -341,6 +360,13 @@ describe("pont_network_deterministic", () => {
 				.accountsStrict({
 					dataAccount,
 					ship: ship.publicKey,
+					fundraisingAccount,
+					protocolConfig,
+					feePayerTokenAccount: null,
+					feeVault: null,
+					tokenProgram: null,
+					feeEscrow: null,
+					shipAccount: shipAccountAddress,
 				})
 				.signers([ship])
 				.rpc();
@@ -365,13 +391,8 @@ describe("pont_network_deterministic", () => {
 		}
 	
 		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(8);
-	
-		for (let i = 0; i < 8; i++) {
-			const fingerprintBuffer = Buffer.from(account.fingerprints[i][0]);
-			const fingerprintHex = fingerprintBuffer.toString('hex');
-			console.log(`Data Fingerprint ${i + 1}: `, fingerprintHex);
-		}
+		expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(8);
+		console.log("Fingerprint tree root: ", currentRoot(account.fingerprintTree).toString('hex'));
 	});
 
     it("Adds a Data Fingerprint deterministically", async () => {
@@ -415,6 +436,13 @@ describe("pont_network_deterministic", () => {
 			.accountsStrict({
 				dataAccount,
 				ship: ship.publicKey,
+				fundraisingAccount,
+				protocolConfig,
+				feePayerTokenAccount: null,
+				feeVault: null,
+				tokenProgram: null,
+				feeEscrow: null,
+				shipAccount: shipAccountAddress,
 			})
 			.signers([ship])
 			.rpc();
@@ -422,16 +450,10 @@ describe("pont_network_deterministic", () => {
 		console.log("Data Fingerprint added with transaction signature", tx);
 
 		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(1);
-
-		// Convert byte array (number[]) to Buffer
-		const fingerprintBuffer = Buffer.from(account.fingerprints[0][0]);
+		expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(1);
 
-		// Convert Buffer to hex string
-		const fingerprintHex = fingerprintBuffer.toString('hex');
-		console.log("Data Fingerprint: ", fingerprintHex);
-
-		expect(fingerprintHex).to.equal(encryptedDataFingerprint);
+		deterministicLeaves = [Buffer.from(encryptedDataFingerprint, 'hex')];
+		expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(deterministicLeaves)).toString('hex'));
 	});
 
     it("Adds Multiple Data Fingerprints deterministically", async () => {
@@ -476,6 +498,13 @@ describe("pont_network_deterministic", () => {
 			.accountsStrict({
 				dataAccount,
 				ship: ship.publicKey,
+				fundraisingAccount,
+				protocolConfig,
+				feePayerTokenAccount: null,
+				feeVault: null,
+				tokenProgram: null,
+				feeEscrow: null,
+				shipAccount: shipAccountAddress,
 			})
 			.signers([ship])
 			.rpc();
@@ -483,10 +512,11 @@ describe("pont_network_deterministic", () => {
 		console.log("Data Fingerprint added with transaction signature", tx);
 
 		const account = await program.account.dataAccount.fetch(dataAccount);
-		expect(account.fingerprints.length).to.equal(4);
-		expect(Buffer.from(account.fingerprints[1][0]).toString('hex')).to.equal(encryptedDataFingerprint[0]);
-		expect(Buffer.from(account.fingerprints[2][0]).toString('hex')).to.equal(encryptedDataFingerprint[1]);
-		expect(Buffer.from(account.fingerprints[3][0]).toString('hex')).to.equal(encryptedDataFingerprint[2]);
+		expect(account.fingerprintTree.nextIndex.toNumber()).to.equal(4);
+
+		// Appended after the fingerprint of the previous test
+		const leaves = [...deterministicLeaves, ...encryptedDataFingerprint.map((fingerprint) => Buffer.from(fingerprint, 'hex'))];
+		expect(currentRoot(account.fingerprintTree).toString('hex')).to.equal((await merkleRoot(leaves)).toString('hex'));
 	});
 
     // Encrypt data